@@ -0,0 +1,233 @@
+use redb::{ReadableTable, TableDefinition};
+use rusty_le::bplus_tree::BPlusTree;
+use std::time::{Duration, Instant};
+
+const DEFAULT_KEYS: usize = 100_000;
+const VALUE_SIZE: usize = 32;
+const RANGE_SCANS: usize = 1_000;
+const RANGE_WIDTH: u64 = 100;
+
+const TABLE: TableDefinition<u64, &[u8]> = TableDefinition::new("bench");
+
+/// One key-value store under test, driven through the same workloads
+trait Engine {
+    fn name(&self) -> &'static str;
+    fn load(&mut self, entries: &[(u64, Vec<u8>)]);
+    /// Returns whether the key was found
+    fn get(&self, key: u64) -> bool;
+    /// Returns the number of entries visited
+    fn scan(&self) -> usize;
+    /// Returns the number of entries in `[start, end)`
+    fn range(&self, start: u64, end: u64) -> usize;
+}
+
+struct RustyLe(BPlusTree<u64, Vec<u8>>);
+
+impl Engine for RustyLe {
+    fn name(&self) -> &'static str {
+        "rusty-le"
+    }
+
+    fn load(&mut self, entries: &[(u64, Vec<u8>)]) {
+        for (key, value) in entries {
+            self.0.insert(*key, value.clone());
+        }
+    }
+
+    fn get(&self, key: u64) -> bool {
+        self.0.contains_key(&key)
+    }
+
+    fn scan(&self) -> usize {
+        self.0.iter().count()
+    }
+
+    fn range(&self, start: u64, end: u64) -> usize {
+        self.0.range(start..end).count()
+    }
+}
+
+struct Sled(sled::Db);
+
+impl Engine for Sled {
+    fn name(&self) -> &'static str {
+        "sled"
+    }
+
+    fn load(&mut self, entries: &[(u64, Vec<u8>)]) {
+        for (key, value) in entries {
+            self.0.insert(key.to_be_bytes(), value.as_slice()).unwrap();
+        }
+    }
+
+    fn get(&self, key: u64) -> bool {
+        self.0.get(key.to_be_bytes()).unwrap().is_some()
+    }
+
+    fn scan(&self) -> usize {
+        self.0.iter().count()
+    }
+
+    fn range(&self, start: u64, end: u64) -> usize {
+        self.0.range(start.to_be_bytes()..end.to_be_bytes()).count()
+    }
+}
+
+struct Redb(redb::Database);
+
+impl Engine for Redb {
+    fn name(&self) -> &'static str {
+        "redb"
+    }
+
+    fn load(&mut self, entries: &[(u64, Vec<u8>)]) {
+        let txn = self.0.begin_write().unwrap();
+        {
+            let mut table = txn.open_table(TABLE).unwrap();
+            for (key, value) in entries {
+                table.insert(key, value.as_slice()).unwrap();
+            }
+        }
+        txn.commit().unwrap();
+    }
+
+    fn get(&self, key: u64) -> bool {
+        let txn = self.0.begin_read().unwrap();
+        let table = txn.open_table(TABLE).unwrap();
+        table.get(key).unwrap().is_some()
+    }
+
+    fn scan(&self) -> usize {
+        let txn = self.0.begin_read().unwrap();
+        let table = txn.open_table(TABLE).unwrap();
+        table.iter().unwrap().count()
+    }
+
+    fn range(&self, start: u64, end: u64) -> usize {
+        let txn = self.0.begin_read().unwrap();
+        let table = txn.open_table(TABLE).unwrap();
+        table.range(start..end).unwrap().count()
+    }
+}
+
+/// Small deterministic xorshift generator so every engine sees the same keys
+struct XorShift(u64);
+
+impl XorShift {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+}
+
+struct Report {
+    engine: &'static str,
+    load: Duration,
+    gets: Duration,
+    found: usize,
+    scan: Duration,
+    ranges: Duration,
+}
+
+fn run(engine: &mut dyn Engine, entries: &[(u64, Vec<u8>)], lookups: &[u64]) -> Report {
+    let start = Instant::now();
+    engine.load(entries);
+    let load = start.elapsed();
+
+    let start = Instant::now();
+    let found = lookups.iter().filter(|&&key| engine.get(key)).count();
+    let gets = start.elapsed();
+
+    let start = Instant::now();
+    let scanned = engine.scan();
+    let scan = start.elapsed();
+    assert!(scanned <= entries.len());
+
+    let start = Instant::now();
+    for &key in lookups.iter().take(RANGE_SCANS) {
+        engine.range(key, key + RANGE_WIDTH);
+    }
+    let ranges = start.elapsed();
+
+    Report {
+        engine: engine.name(),
+        load,
+        gets,
+        found,
+        scan,
+        ranges,
+    }
+}
+
+fn ops_per_sec(ops: usize, elapsed: Duration) -> String {
+    format!("{:.0}", ops as f64 / elapsed.as_secs_f64().max(f64::EPSILON))
+}
+
+fn parse_keys(args: &[String]) -> Result<usize, String> {
+    match args.iter().position(|a| a == "--keys") {
+        Some(i) => args
+            .get(i + 1)
+            .ok_or("--keys requires a value")?
+            .parse()
+            .map_err(|e| format!("invalid --keys: {}", e)),
+        None => Ok(DEFAULT_KEYS),
+    }
+}
+
+fn main() {
+    let args: Vec<String> = std::env::args().collect();
+    let keys = match parse_keys(&args) {
+        Ok(keys) => keys,
+        Err(e) => {
+            eprintln!("{}\nusage: kv-bench [--keys N]", e);
+            std::process::exit(2);
+        }
+    };
+
+    let mut rng = XorShift(0x9E37_79B9_7F4A_7C15);
+    let entries: Vec<(u64, Vec<u8>)> = (0..keys as u64)
+        .map(|i| (rng.next() % (keys as u64 * 4), i.to_le_bytes().repeat(VALUE_SIZE / 8)))
+        .collect();
+    let lookups: Vec<u64> = entries.iter().map(|(k, _)| *k).rev().collect();
+    let mut unique: Vec<u64> = lookups.clone();
+    unique.sort_unstable();
+    unique.dedup();
+
+    let sled_dir = std::env::temp_dir().join(format!("kv-bench-sled-{}", std::process::id()));
+    let mut engines: Vec<Box<dyn Engine>> = vec![
+        Box::new(RustyLe(BPlusTree::new())),
+        Box::new(Sled(sled::Config::new().path(&sled_dir).temporary(true).open().unwrap())),
+        Box::new(Redb(
+            redb::Database::builder()
+                .create_with_backend(redb::backends::InMemoryBackend::new())
+                .unwrap(),
+        )),
+    ];
+
+    println!(
+        "{} random u64 keys ({} distinct), {}-byte values, {} range scans of width {}",
+        keys,
+        unique.len(),
+        VALUE_SIZE,
+        RANGE_SCANS,
+        RANGE_WIDTH
+    );
+    println!("sled uses a temporary directory; redb uses its in-memory backend and loads in one transaction\n");
+    println!("| engine | load ops/s | get ops/s | found | scan entries/s | range scans/s |");
+    println!("|---|---|---|---|---|---|");
+    for engine in engines.iter_mut() {
+        let r = run(engine.as_mut(), &entries, &lookups);
+        println!(
+            "| {} | {} | {} | {}/{} | {} | {} |",
+            r.engine,
+            ops_per_sec(keys, r.load),
+            ops_per_sec(lookups.len(), r.gets),
+            r.found,
+            lookups.len(),
+            ops_per_sec(unique.len(), r.scan),
+            ops_per_sec(RANGE_SCANS.min(lookups.len()), r.ranges)
+        );
+    }
+}
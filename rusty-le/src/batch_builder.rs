@@ -0,0 +1,507 @@
+use arrow::array::{
+    ArrayRef, BooleanArray, BooleanBufferBuilder, Float64Array, Int32Array, Int64Array,
+    RecordBatch, StringArray,
+};
+use arrow::buffer::{Buffer, NullBuffer, OffsetBuffer};
+use arrow::datatypes::{ArrowNativeType, DataType, Field, SchemaRef};
+use arrow::error::ArrowError;
+use std::mem;
+use std::sync::Arc;
+
+use crate::profile::{self, Phase};
+
+/// Default number of rows per emitted batch
+const DEFAULT_MAX_ROWS: usize = 8192;
+
+/// A single cell value appended through `BatchBuilder`
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Value<'a> {
+    Null,
+    Int32(i32),
+    Int64(i64),
+    Float64(f64),
+    Utf8(&'a str),
+    Boolean(bool),
+}
+
+impl Value<'_> {
+    fn data_type(&self) -> Option<DataType> {
+        match self {
+            Value::Null => None,
+            Value::Int32(_) => Some(DataType::Int32),
+            Value::Int64(_) => Some(DataType::Int64),
+            Value::Float64(_) => Some(DataType::Float64),
+            Value::Utf8(_) => Some(DataType::Utf8),
+            Value::Boolean(_) => Some(DataType::Boolean),
+        }
+    }
+
+    /// Approximate number of buffer bytes the value occupies once appended
+    fn byte_size(&self) -> usize {
+        match self {
+            Value::Null => 0,
+            Value::Int32(_) => 4,
+            Value::Int64(_) | Value::Float64(_) => 8,
+            Value::Utf8(s) => s.len() + 4,
+            Value::Boolean(_) => 1,
+        }
+    }
+}
+
+/// Row-oriented builder that turns appended rows into RecordBatches
+///
+/// Appends each column into plain buffers and emits a batch whenever the row
+/// or byte threshold is reached. Emitting moves those buffers into the batch's
+/// arrays without copying, so to reuse them the caller hands finished batches
+/// back through `recycle`; the next batch then fills the reclaimed buffers
+/// instead of allocating fresh ones.
+pub struct BatchBuilder {
+    schema: SchemaRef,
+    /// Buffers of the batch being built, taken on its first row
+    columns: Vec<Column>,
+    /// Buffers reclaimed by `recycle`, ready for the next batch
+    spare: Option<Vec<Column>>,
+    max_rows: usize,
+    max_bytes: usize,
+    rows: usize,
+    bytes: usize,
+}
+
+impl BatchBuilder {
+    /// Create a builder for `schema` emitting batches of `DEFAULT_MAX_ROWS` rows
+    ///
+    /// Fails if a field has a type `Value` cannot represent.
+    pub fn new(schema: SchemaRef) -> Result<Self, ArrowError> {
+        for field in schema.fields() {
+            if Column::new(field, 0).is_none() {
+                return Err(ArrowError::NotYetImplemented(format!(
+                    "column '{}' has type {}, which BatchBuilder cannot append",
+                    field.name(),
+                    field.data_type()
+                )));
+            }
+        }
+        Ok(BatchBuilder {
+            schema,
+            columns: Vec::new(),
+            spare: None,
+            max_rows: DEFAULT_MAX_ROWS,
+            max_bytes: usize::MAX,
+            rows: 0,
+            bytes: 0,
+        })
+    }
+
+    /// Emit a batch once this many rows have been appended
+    pub fn with_max_rows(mut self, max_rows: usize) -> Self {
+        self.max_rows = max_rows.max(1);
+        self
+    }
+
+    /// Emit a batch once roughly this many bytes have been appended
+    pub fn with_max_bytes(mut self, max_bytes: usize) -> Self {
+        self.max_bytes = max_bytes.max(1);
+        self
+    }
+
+    /// Append one row, returning a finished batch if a threshold was reached
+    pub fn append_row(&mut self, row: &[Value]) -> Result<Option<RecordBatch>, ArrowError> {
+        self.validate_row(row)?;
+
+        if self.columns.is_empty() {
+            self.columns = match self.spare.take() {
+                Some(columns) => columns,
+                None => self.new_columns(),
+            };
+        }
+        for (column, value) in self.columns.iter_mut().zip(row) {
+            column.append(value);
+            self.bytes += value.byte_size();
+        }
+        self.rows += 1;
+
+        if self.rows >= self.max_rows || self.bytes >= self.max_bytes {
+            self.flush()
+        } else {
+            Ok(None)
+        }
+    }
+
+    /// Finish any buffered rows into a batch
+    pub fn flush(&mut self) -> Result<Option<RecordBatch>, ArrowError> {
+        if self.rows == 0 {
+            return Ok(None);
+        }
+
+        let _scope = profile::scope(Phase::BatchBuild);
+        let columns = mem::take(&mut self.columns);
+        self.rows = 0;
+        self.bytes = 0;
+        let arrays = columns
+            .into_iter()
+            .map(Column::finish)
+            .collect::<Result<Vec<_>, _>>()?;
+        RecordBatch::try_new(self.schema.clone(), arrays).map(Some)
+    }
+
+    /// Hand back a batch emitted by this builder once it is no longer needed
+    ///
+    /// Buffers that nothing else references become the storage of a later
+    /// batch, so a loop that drops each batch before the next one is emitted
+    /// stops allocating after the first. Columns still shared elsewhere, for
+    /// instance through a clone or a slice of the batch, are left alone.
+    pub fn recycle(&mut self, batch: RecordBatch) {
+        if self.spare.is_some() || batch.schema() != self.schema {
+            return;
+        }
+        let arrays = batch.columns().to_vec();
+        drop(batch);
+        let capacity = self.capacity();
+        let columns = self
+            .schema
+            .fields()
+            .iter()
+            .zip(arrays)
+            .map(|(field, array)| {
+                Column::reclaim(field, array)
+                    .unwrap_or_else(|| Column::new(field, capacity).expect("checked in new"))
+            })
+            .collect();
+        self.spare = Some(columns);
+    }
+
+    fn validate_row(&self, row: &[Value]) -> Result<(), ArrowError> {
+        if row.len() != self.schema.fields().len() {
+            return Err(ArrowError::InvalidArgumentError(format!(
+                "expected {} values per row, got {}",
+                self.schema.fields().len(),
+                row.len()
+            )));
+        }
+
+        for (field, value) in self.schema.fields().iter().zip(row) {
+            match value.data_type() {
+                None if !field.is_nullable() => {
+                    return Err(ArrowError::InvalidArgumentError(format!(
+                        "column '{}' is not nullable",
+                        field.name()
+                    )));
+                }
+                Some(data_type) if &data_type != field.data_type() => {
+                    return Err(ArrowError::InvalidArgumentError(format!(
+                        "column '{}' expects {}, got {}",
+                        field.name(),
+                        field.data_type(),
+                        data_type
+                    )));
+                }
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    /// Rows to preallocate per column, capped so huge thresholds stay cheap
+    fn capacity(&self) -> usize {
+        self.max_rows.min(DEFAULT_MAX_ROWS)
+    }
+
+    fn new_columns(&self) -> Vec<Column> {
+        let capacity = self.capacity();
+        self.schema
+            .fields()
+            .iter()
+            .map(|field| Column::new(field, capacity).expect("checked in new"))
+            .collect()
+    }
+}
+
+/// Buffers one column's values are appended to
+struct Column {
+    values: Values,
+    /// One bit per row, cleared for nulls; absent for non-nullable fields
+    validity: Option<BooleanBufferBuilder>,
+}
+
+enum Values {
+    Int32(Vec<i32>),
+    Int64(Vec<i64>),
+    Float64(Vec<f64>),
+    Utf8 { offsets: Vec<i32>, data: Vec<u8> },
+    Boolean(BooleanBufferBuilder),
+}
+
+impl Column {
+    /// Empty buffers for `capacity` rows, or `None` if `Value` has no variant
+    /// for the field's type
+    fn new(field: &Field, capacity: usize) -> Option<Column> {
+        let values = match field.data_type() {
+            DataType::Int32 => Values::Int32(Vec::with_capacity(capacity)),
+            DataType::Int64 => Values::Int64(Vec::with_capacity(capacity)),
+            DataType::Float64 => Values::Float64(Vec::with_capacity(capacity)),
+            DataType::Utf8 => {
+                let mut offsets = Vec::with_capacity(capacity + 1);
+                offsets.push(0);
+                Values::Utf8 {
+                    offsets,
+                    data: Vec::new(),
+                }
+            }
+            DataType::Boolean => Values::Boolean(BooleanBufferBuilder::new(capacity)),
+            _ => return None,
+        };
+        let validity = field
+            .is_nullable()
+            .then(|| BooleanBufferBuilder::new(capacity));
+        Some(Column { values, validity })
+    }
+
+    /// Append a value that has already been checked against the column type
+    fn append(&mut self, value: &Value) {
+        if let Some(validity) = &mut self.validity {
+            validity.append(!matches!(value, Value::Null));
+        }
+        match (&mut self.values, value) {
+            (Values::Int32(values), Value::Int32(v)) => values.push(*v),
+            (Values::Int64(values), Value::Int64(v)) => values.push(*v),
+            (Values::Float64(values), Value::Float64(v)) => values.push(*v),
+            (Values::Utf8 { offsets, data }, Value::Utf8(v)) => {
+                data.extend_from_slice(v.as_bytes());
+                offsets.push(utf8_offset(data));
+            }
+            (Values::Boolean(values), Value::Boolean(v)) => values.append(*v),
+            (Values::Int32(values), Value::Null) => values.push(0),
+            (Values::Int64(values), Value::Null) => values.push(0),
+            (Values::Float64(values), Value::Null) => values.push(0.0),
+            (Values::Utf8 { offsets, data }, Value::Null) => offsets.push(utf8_offset(data)),
+            (Values::Boolean(values), Value::Null) => values.append(false),
+            _ => unreachable!("validate_row rejects values of the wrong type"),
+        }
+    }
+
+    /// Move the buffers into an array without copying them
+    fn finish(self) -> Result<ArrayRef, ArrowError> {
+        let nulls = self.validity.map(|mut bits| NullBuffer::new(bits.finish()));
+        let array: ArrayRef = match self.values {
+            Values::Int32(values) => Arc::new(Int32Array::try_new(values.into(), nulls)?),
+            Values::Int64(values) => Arc::new(Int64Array::try_new(values.into(), nulls)?),
+            Values::Float64(values) => Arc::new(Float64Array::try_new(values.into(), nulls)?),
+            Values::Utf8 { offsets, data } => Arc::new(StringArray::try_new(
+                OffsetBuffer::new(offsets.into()),
+                Buffer::from_vec(data),
+                nulls,
+            )?),
+            Values::Boolean(mut values) => Arc::new(BooleanArray::new(values.finish(), nulls)),
+        };
+        Ok(array)
+    }
+
+    /// Emptied buffers taken back from an array built by `finish`, or `None`
+    /// if any of them is still shared
+    fn reclaim(field: &Field, array: ArrayRef) -> Option<Column> {
+        let data = array.to_data();
+        drop(array);
+        if data.offset() != 0 || data.data_type() != field.data_type() {
+            return None;
+        }
+        let mut buffers = data.buffers().to_vec().into_iter();
+        let nulls = data.nulls().map(|nulls| nulls.inner().inner().clone());
+        drop(data);
+
+        // Each buffer must be moved out, as a clone would keep it shared
+        let values = match field.data_type() {
+            DataType::Int32 => Values::Int32(reclaim_vec(buffers.next()?)?),
+            DataType::Int64 => Values::Int64(reclaim_vec(buffers.next()?)?),
+            DataType::Float64 => Values::Float64(reclaim_vec(buffers.next()?)?),
+            DataType::Utf8 => {
+                let mut offsets = reclaim_vec(buffers.next()?)?;
+                offsets.push(0);
+                Values::Utf8 {
+                    offsets,
+                    data: reclaim_vec(buffers.next()?)?,
+                }
+            }
+            DataType::Boolean => Values::Boolean(reclaim_bits(buffers.next()?)?),
+            _ => return None,
+        };
+        let validity = match (field.is_nullable(), nulls) {
+            (false, _) => None,
+            (true, Some(bits)) => Some(reclaim_bits(bits)?),
+            (true, None) => Some(BooleanBufferBuilder::new(0)),
+        };
+        Some(Column { values, validity })
+    }
+}
+
+/// End offset of the string just appended to `data`
+fn utf8_offset(data: &[u8]) -> i32 {
+    i32::try_from(data.len()).expect("string column exceeds i32 offsets")
+}
+
+/// An emptied `Vec` owning the allocation behind `buffer`
+fn reclaim_vec<T: ArrowNativeType>(buffer: Buffer) -> Option<Vec<T>> {
+    let mut values = buffer.into_vec::<T>().ok()?;
+    values.clear();
+    Some(values)
+}
+
+/// An emptied bitmap owning the allocation behind `buffer`
+fn reclaim_bits(buffer: Buffer) -> Option<BooleanBufferBuilder> {
+    let mut bits = buffer.into_mutable().ok()?;
+    bits.clear();
+    Some(BooleanBufferBuilder::new_from_buffer(bits, 0))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use arrow::array::{Array, Int64Array, StringArray};
+    use arrow::datatypes::{Field, Schema};
+    use std::sync::Arc;
+
+    fn schema() -> SchemaRef {
+        Arc::new(Schema::new(vec![
+            Field::new("id", DataType::Int64, false),
+            Field::new("name", DataType::Utf8, true),
+        ]))
+    }
+
+    #[test]
+    fn test_emits_at_row_threshold() {
+        let mut builder = BatchBuilder::new(schema()).unwrap().with_max_rows(2);
+
+        assert!(builder
+            .append_row(&[Value::Int64(1), Value::Utf8("a")])
+            .unwrap()
+            .is_none());
+        let batch = builder
+            .append_row(&[Value::Int64(2), Value::Null])
+            .unwrap()
+            .expect("second row fills the batch");
+        assert_eq!(batch.num_rows(), 2);
+        assert!(batch.column(1).is_null(1));
+
+        builder
+            .append_row(&[Value::Int64(3), Value::Utf8("c")])
+            .unwrap();
+        let rest = builder.flush().unwrap().unwrap();
+        let ids = rest
+            .column(0)
+            .as_any()
+            .downcast_ref::<Int64Array>()
+            .unwrap();
+        let names = rest
+            .column(1)
+            .as_any()
+            .downcast_ref::<StringArray>()
+            .unwrap();
+        assert_eq!(ids.value(0), 3);
+        assert_eq!(names.value(0), "c");
+        assert!(builder.flush().unwrap().is_none());
+    }
+
+    #[test]
+    fn test_emits_at_byte_threshold() {
+        let mut builder = BatchBuilder::new(schema()).unwrap().with_max_bytes(20);
+
+        assert!(builder
+            .append_row(&[Value::Int64(1), Value::Utf8("abc")])
+            .unwrap()
+            .is_none());
+        let batch = builder
+            .append_row(&[Value::Int64(2), Value::Utf8("def")])
+            .unwrap();
+        assert_eq!(batch.map(|b| b.num_rows()), Some(2));
+    }
+
+    #[test]
+    fn test_unbounded_rows_with_byte_threshold() {
+        let mut builder = BatchBuilder::new(schema())
+            .unwrap()
+            .with_max_rows(usize::MAX)
+            .with_max_bytes(32);
+
+        let mut emitted = None;
+        for id in 0..4 {
+            emitted = builder
+                .append_row(&[Value::Int64(id), Value::Null])
+                .unwrap();
+        }
+        assert_eq!(emitted.map(|b| b.num_rows()), Some(4));
+    }
+
+    #[test]
+    fn test_rejects_invalid_rows() {
+        let mut builder = BatchBuilder::new(schema()).unwrap();
+
+        assert!(builder.append_row(&[Value::Int64(1)]).is_err());
+        assert!(builder
+            .append_row(&[Value::Null, Value::Utf8("a")])
+            .is_err());
+        assert!(builder
+            .append_row(&[Value::Int32(1), Value::Utf8("a")])
+            .is_err());
+        assert!(builder.flush().unwrap().is_none());
+    }
+
+    #[test]
+    fn test_rejects_unsupported_column_types() {
+        let schema = Arc::new(Schema::new(vec![
+            Field::new("id", DataType::Int64, false),
+            Field::new("day", DataType::Date32, true),
+        ]));
+        assert!(BatchBuilder::new(schema).is_err());
+    }
+
+    /// Address of the first buffer behind `column` of `batch`
+    fn buffer_ptr(batch: &RecordBatch, column: usize) -> *const u8 {
+        batch.column(column).to_data().buffers()[0].as_ptr()
+    }
+
+    #[test]
+    fn test_recycled_buffers_back_the_next_batch() {
+        let mut builder = BatchBuilder::new(schema()).unwrap().with_max_rows(2);
+        let row = [Value::Int64(1), Value::Utf8("a")];
+
+        builder.append_row(&row).unwrap();
+        let first = builder.append_row(&row).unwrap().unwrap();
+        let (ids, names) = (buffer_ptr(&first, 0), buffer_ptr(&first, 1));
+        builder.recycle(first);
+
+        builder.append_row(&[Value::Int64(2), Value::Null]).unwrap();
+        let second = builder.append_row(&row).unwrap().unwrap();
+        assert_eq!(buffer_ptr(&second, 0), ids);
+        assert_eq!(buffer_ptr(&second, 1), names);
+        let values = second
+            .column(0)
+            .as_any()
+            .downcast_ref::<Int64Array>()
+            .unwrap();
+        assert_eq!(values.values(), &[2, 1]);
+        assert!(second.column(1).is_null(0));
+    }
+
+    #[test]
+    fn test_shared_batches_are_not_recycled() {
+        let mut builder = BatchBuilder::new(schema()).unwrap().with_max_rows(1);
+
+        let first = builder
+            .append_row(&[Value::Int64(1), Value::Null])
+            .unwrap()
+            .unwrap();
+        let kept = first.clone();
+        builder.recycle(first);
+
+        let second = builder
+            .append_row(&[Value::Int64(2), Value::Null])
+            .unwrap()
+            .unwrap();
+        assert_ne!(buffer_ptr(&second, 0), buffer_ptr(&kept, 0));
+        let ids = kept
+            .column(0)
+            .as_any()
+            .downcast_ref::<Int64Array>()
+            .unwrap();
+        assert_eq!(ids.value(0), 1);
+    }
+}
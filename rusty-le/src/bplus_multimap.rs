@@ -0,0 +1,207 @@
+use crate::bplus_tree::{self, BPlusTree};
+use std::borrow::Borrow;
+use std::ops::{Bound, RangeBounds};
+use std::slice;
+
+/// B+ tree that keeps every value inserted under a key
+///
+/// Values for the same key are stored together in one leaf entry, in insertion
+/// order, so duplicates never split across leaves and a key is located with a
+/// single descent.
+pub struct BPlusMultiMap<K = i32, V = String> {
+    tree: BPlusTree<K, Vec<V>>,
+    len: usize,
+}
+
+impl<K: Ord + Clone, V> BPlusMultiMap<K, V> {
+    /// Create an empty multimap
+    pub fn new() -> Self {
+        BPlusMultiMap {
+            tree: BPlusTree::new(),
+            len: 0,
+        }
+    }
+
+    /// Create an empty multimap backed by a tree of the given minimum degree
+    pub fn with_degree(min_degree: usize) -> Self {
+        BPlusMultiMap {
+            tree: BPlusTree::with_degree(min_degree),
+            len: 0,
+        }
+    }
+
+    /// Add a value under `key`, keeping any values already stored there
+    pub fn insert(&mut self, key: K, value: V) {
+        self.tree.entry(key).or_default().push(value);
+        self.len += 1;
+    }
+
+    /// All values stored under `key`, in insertion order
+    pub fn get_all<Q>(&self, key: &Q) -> &[V]
+    where
+        K: Borrow<Q>,
+        Q: Ord + ?Sized,
+    {
+        self.tree
+            .range((Bound::Included(key), Bound::Included(key)))
+            .next()
+            .map_or(&[], |(_, values)| values.as_slice())
+    }
+
+    /// Remove and return all values stored under `key`
+    pub fn remove_all<Q>(&mut self, key: &Q) -> Vec<V>
+    where
+        K: Borrow<Q>,
+        Q: Ord + ?Sized,
+    {
+        let values = self.tree.remove(key).unwrap_or_default();
+        self.len -= values.len();
+        values
+    }
+
+    /// Whether at least one value is stored under `key`
+    pub fn contains_key<Q>(&self, key: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: Ord + ?Sized,
+    {
+        self.tree.contains_key(key)
+    }
+
+    /// Total number of values across all keys
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Whether the multimap holds no values
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Number of distinct keys
+    pub fn key_count(&self) -> usize {
+        self.tree.len()
+    }
+
+    /// Iterate over every `(key, value)` pair whose key falls within `range`,
+    /// yielding each duplicate in insertion order
+    pub fn range<T, R>(&self, range: R) -> Iter<'_, K, V>
+    where
+        K: Borrow<T>,
+        T: Ord + ?Sized,
+        R: RangeBounds<T>,
+    {
+        Iter {
+            keys: self.tree.range(range),
+            front: None,
+            back: None,
+        }
+    }
+
+    /// Iterate over every `(key, value)` pair in ascending key order
+    pub fn iter(&self) -> Iter<'_, K, V> {
+        Iter {
+            keys: self.tree.iter(),
+            front: None,
+            back: None,
+        }
+    }
+}
+
+impl<K: Ord + Clone, V> Default for BPlusMultiMap<K, V> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Iterator over `(&key, &value)` pairs of a multimap, one per stored value
+pub struct Iter<'a, K, V> {
+    keys: bplus_tree::Iter<'a, K, Vec<V>>,
+    front: Option<(&'a K, slice::Iter<'a, V>)>,
+    back: Option<(&'a K, slice::Iter<'a, V>)>,
+}
+
+impl<'a, K, V> Iterator for Iter<'a, K, V> {
+    type Item = (&'a K, &'a V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some((key, values)) = &mut self.front {
+                if let Some(value) = values.next() {
+                    return Some((*key, value));
+                }
+            }
+            match self.keys.next() {
+                Some((key, values)) => self.front = Some((key, values.iter())),
+                // The key cursors have met; finish whatever the back half started
+                None => {
+                    let (key, values) = self.back.as_mut()?;
+                    return values.next().map(|value| (*key, value));
+                }
+            }
+        }
+    }
+}
+
+impl<K, V> DoubleEndedIterator for Iter<'_, K, V> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some((key, values)) = &mut self.back {
+                if let Some(value) = values.next_back() {
+                    return Some((*key, value));
+                }
+            }
+            match self.keys.next_back() {
+                Some((key, values)) => self.back = Some((key, values.iter())),
+                None => {
+                    let (key, values) = self.front.as_mut()?;
+                    return values.next_back().map(|value| (*key, value));
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_duplicates_are_kept() {
+        let mut map = BPlusMultiMap::new();
+        for (k, v) in [(3, "c1"), (1, "a1"), (3, "c2"), (2, "b1"), (3, "c3"), (1, "a2")] {
+            map.insert(k, v.to_string());
+        }
+
+        assert_eq!(map.len(), 6);
+        assert_eq!(map.key_count(), 3);
+        assert_eq!(map.get_all(&3), ["c1", "c2", "c3"]);
+        assert!(map.get_all(&4).is_empty());
+
+        assert_eq!(map.remove_all(&1), ["a1", "a2"]);
+        assert!(!map.contains_key(&1));
+        assert!(map.remove_all(&1).is_empty());
+        assert_eq!(map.len(), 4);
+    }
+
+    #[test]
+    fn test_range_yields_every_duplicate() {
+        let mut map = BPlusMultiMap::new();
+        for i in 0..10 {
+            map.insert(i % 5, i);
+        }
+
+        let pairs: Vec<(i32, i32)> = map.range(1..=3).map(|(k, v)| (*k, *v)).collect();
+        assert_eq!(pairs, vec![(1, 1), (1, 6), (2, 2), (2, 7), (3, 3), (3, 8)]);
+        let reversed: Vec<i32> = map.range(1..=3).rev().map(|(_, v)| *v).collect();
+        assert_eq!(reversed, vec![8, 3, 7, 2, 6, 1]);
+        assert_eq!(map.iter().count(), 10);
+
+        // Both ends share a key's values without yielding any twice
+        let mut iter = map.range(2..3);
+        assert_eq!(iter.next(), Some((&2, &2)));
+        assert_eq!(iter.next_back(), Some((&2, &7)));
+        assert_eq!(iter.next(), None);
+        assert_eq!(iter.next_back(), None);
+    }
+}
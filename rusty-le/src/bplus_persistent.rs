@@ -0,0 +1,655 @@
+use crate::bplus_tree::{self, NaturalOrder, DEFAULT_MIN_DEGREE};
+use std::borrow::Borrow;
+use std::fmt;
+use std::mem;
+use std::ops::{Bound, RangeBounds};
+use std::sync::Arc;
+
+/// Node of a persistent tree, never modified once it is shared
+#[derive(Clone)]
+enum Node<K, V> {
+    Leaf {
+        entries: Vec<(K, V)>,
+    },
+    Internal {
+        keys: Vec<K>,
+        children: Vec<Child<K, V>>,
+    },
+}
+
+impl<K, V> Node<K, V> {
+    fn num_keys(&self) -> usize {
+        match self {
+            Node::Leaf { entries } => entries.len(),
+            Node::Internal { keys, .. } => keys.len(),
+        }
+    }
+}
+
+/// Link to a child node, shared by every version that contains it
+type Child<K, V> = Arc<Node<K, V>>;
+
+/// A node rebuilt by an insertion, split in two if it overflowed
+enum Insertion<K, V> {
+    Fit(Node<K, V>),
+    Split(Node<K, V>, K, Node<K, V>),
+}
+
+/// Immutable B+ tree whose updates return a new tree
+///
+/// `insert` and `remove` copy only the nodes on the path to the affected
+/// leaf; everything else is shared with the original through `Arc`, so both
+/// versions stay usable and cloning a tree to keep a snapshot is O(1). Unlike
+/// `BPlusTree`, leaves are not chained, since a shared leaf has different
+/// neighbours in different versions; iterators walk down from the root instead.
+pub struct PersistentBPlusTree<K = i32, V = String> {
+    root: Child<K, V>,
+    len: usize,
+    min_degree: usize,
+}
+
+impl<K: Ord + Clone, V: Clone> PersistentBPlusTree<K, V> {
+    /// Create an empty tree
+    pub fn new() -> Self {
+        Self::with_degree(DEFAULT_MIN_DEGREE)
+    }
+
+    /// Create an empty tree whose non-root nodes hold between
+    /// `min_degree - 1` and `2 * min_degree - 1` keys
+    ///
+    /// # Panics
+    ///
+    /// Panics if `min_degree` is less than 2.
+    pub fn with_degree(min_degree: usize) -> Self {
+        assert!(min_degree >= 2, "min_degree must be at least 2, got {}", min_degree);
+        PersistentBPlusTree {
+            root: Arc::new(Node::Leaf { entries: Vec::new() }),
+            len: 0,
+            min_degree,
+        }
+    }
+
+    /// Number of entries in the tree
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Whether the tree holds no entries
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// The value stored under `key`
+    pub fn get<Q>(&self, key: &Q) -> Option<&V>
+    where
+        K: Borrow<Q>,
+        Q: Ord + ?Sized,
+    {
+        let mut node = &*self.root;
+        loop {
+            match node {
+                Node::Internal { keys, children } => {
+                    node = &children[keys.partition_point(|k| k.borrow() <= key)];
+                }
+                Node::Leaf { entries } => {
+                    return entries
+                        .binary_search_by(|(k, _)| k.borrow().cmp(key))
+                        .ok()
+                        .map(|pos| &entries[pos].1);
+                }
+            }
+        }
+    }
+
+    /// Whether `key` is present
+    pub fn contains_key<Q>(&self, key: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: Ord + ?Sized,
+    {
+        self.get(key).is_some()
+    }
+
+    /// A tree with `value` stored under `key`, replacing any previous value
+    pub fn insert(&self, key: K, value: V) -> Self {
+        let (insertion, added) = self.insert_into(&self.root, key, value);
+        let root = match insertion {
+            Insertion::Fit(node) => node,
+            Insertion::Split(left, separator, right) => Node::Internal {
+                keys: vec![separator],
+                children: vec![Arc::new(left), Arc::new(right)],
+            },
+        };
+        PersistentBPlusTree {
+            root: Arc::new(root),
+            len: self.len + usize::from(added),
+            min_degree: self.min_degree,
+        }
+    }
+
+    /// Copy of `node` with the entry added, and whether the key was new
+    fn insert_into(&self, node: &Node<K, V>, key: K, value: V) -> (Insertion<K, V>, bool) {
+        let max_keys = 2 * self.min_degree - 1;
+        match node {
+            Node::Leaf { entries } => {
+                let mut entries = entries.clone();
+                let added = match entries.binary_search_by(|(k, _)| k.cmp(&key)) {
+                    Ok(pos) => {
+                        entries[pos].1 = value;
+                        false
+                    }
+                    Err(pos) => {
+                        entries.insert(pos, (key, value));
+                        true
+                    }
+                };
+                if entries.len() <= max_keys {
+                    return (Insertion::Fit(Node::Leaf { entries }), added);
+                }
+                let right = entries.split_off(entries.len() / 2);
+                let separator = right[0].0.clone();
+                let split = Insertion::Split(
+                    Node::Leaf { entries },
+                    separator,
+                    Node::Leaf { entries: right },
+                );
+                (split, added)
+            }
+            Node::Internal { keys, children } => {
+                let idx = keys.partition_point(|k| *k <= key);
+                let (insertion, added) = self.insert_into(&children[idx], key, value);
+                let mut keys = keys.clone();
+                let mut children = children.clone();
+                match insertion {
+                    Insertion::Fit(child) => children[idx] = Arc::new(child),
+                    Insertion::Split(left, separator, right) => {
+                        children[idx] = Arc::new(left);
+                        children.insert(idx + 1, Arc::new(right));
+                        keys.insert(idx, separator);
+                    }
+                }
+                if keys.len() <= max_keys {
+                    return (Insertion::Fit(Node::Internal { keys, children }), added);
+                }
+                let mid = keys.len() / 2;
+                let right_keys = keys.split_off(mid + 1);
+                let separator = keys.pop().expect("an overflowing node has a middle key");
+                let right_children = children.split_off(mid + 1);
+                let split = Insertion::Split(
+                    Node::Internal { keys, children },
+                    separator,
+                    Node::Internal {
+                        keys: right_keys,
+                        children: right_children,
+                    },
+                );
+                (split, added)
+            }
+        }
+    }
+
+    /// A tree without `key`; if it is absent the result shares every node
+    pub fn remove<Q>(&self, key: &Q) -> Self
+    where
+        K: Borrow<Q>,
+        Q: Ord + ?Sized,
+    {
+        let Some(root) = self.remove_from(&self.root, key) else {
+            return self.clone();
+        };
+        let root = match root {
+            // A merge left the root with a single child, which takes its place
+            Node::Internal { mut children, .. } if children.len() == 1 => {
+                children.pop().expect("checked above")
+            }
+            node => Arc::new(node),
+        };
+        PersistentBPlusTree {
+            root,
+            len: self.len - 1,
+            min_degree: self.min_degree,
+        }
+    }
+
+    /// Copy of `node` without `key`, or `None` if the key is absent
+    fn remove_from<Q>(&self, node: &Node<K, V>, key: &Q) -> Option<Node<K, V>>
+    where
+        K: Borrow<Q>,
+        Q: Ord + ?Sized,
+    {
+        match node {
+            Node::Leaf { entries } => {
+                let pos = entries.binary_search_by(|(k, _)| k.borrow().cmp(key)).ok()?;
+                let mut rest = Vec::with_capacity(entries.len() - 1);
+                rest.extend_from_slice(&entries[..pos]);
+                rest.extend_from_slice(&entries[pos + 1..]);
+                Some(Node::Leaf { entries: rest })
+            }
+            Node::Internal { keys, children } => {
+                let idx = keys.partition_point(|k| k.borrow() <= key);
+                let child = self.remove_from(&children[idx], key)?;
+                let mut keys = keys.clone();
+                let mut children = children.clone();
+                if child.num_keys() + 1 < self.min_degree {
+                    self.rebalance(&mut keys, &mut children, idx, child);
+                } else {
+                    children[idx] = Arc::new(child);
+                }
+                Some(Node::Internal { keys, children })
+            }
+        }
+    }
+
+    /// Put back `child`, which fell below the minimum occupancy, by borrowing
+    /// from a sibling that can spare a key or else merging with one
+    fn rebalance(
+        &self,
+        keys: &mut Vec<K>,
+        children: &mut Vec<Child<K, V>>,
+        idx: usize,
+        mut child: Node<K, V>,
+    ) {
+        let can_spare = |sibling: &Node<K, V>| sibling.num_keys() >= self.min_degree;
+        if idx > 0 && can_spare(&children[idx - 1]) {
+            let mut left = Node::clone(&children[idx - 1]);
+            rotate_right(&mut left, &mut keys[idx - 1], &mut child);
+            children[idx - 1] = Arc::new(left);
+            children[idx] = Arc::new(child);
+        } else if idx + 1 < children.len() && can_spare(&children[idx + 1]) {
+            let mut right = Node::clone(&children[idx + 1]);
+            rotate_left(&mut child, &mut keys[idx], &mut right);
+            children[idx] = Arc::new(child);
+            children[idx + 1] = Arc::new(right);
+        } else if idx > 0 {
+            let left = Node::clone(&children[idx - 1]);
+            let separator = keys.remove(idx - 1);
+            children.remove(idx);
+            children[idx - 1] = Arc::new(merge(left, separator, child));
+        } else {
+            let right = Node::clone(&children[idx + 1]);
+            let separator = keys.remove(idx);
+            children.remove(idx + 1);
+            children[idx] = Arc::new(merge(child, separator, right));
+        }
+    }
+
+    /// Iterate over entries in ascending key order
+    pub fn iter(&self) -> Iter<'_, K, V> {
+        Iter {
+            front: Cursor::seek(&self.root, |_| false),
+            end: None,
+        }
+    }
+
+    /// Iterate over the entries whose keys fall within `range`
+    pub fn range<T, R>(&self, range: R) -> Iter<'_, K, V>
+    where
+        K: Borrow<T>,
+        T: Ord + ?Sized,
+        R: RangeBounds<T>,
+    {
+        if bplus_tree::is_empty_range(&NaturalOrder, &range) {
+            return Iter {
+                front: Cursor::exhausted(),
+                end: None,
+            };
+        }
+
+        let front = match range.start_bound() {
+            Bound::Included(s) => Cursor::seek(&self.root, |k| k.borrow() < s),
+            Bound::Excluded(s) => Cursor::seek(&self.root, |k| k.borrow() <= s),
+            Bound::Unbounded => Cursor::seek(&self.root, |_| false),
+        };
+        let end = match range.end_bound() {
+            Bound::Included(e) => Cursor::seek(&self.root, |k| k.borrow() <= e),
+            Bound::Excluded(e) => Cursor::seek(&self.root, |k| k.borrow() < e),
+            Bound::Unbounded => return Iter { front, end: None },
+        };
+        Iter {
+            front,
+            end: (!end.is_end()).then_some((end.leaf, end.pos)),
+        }
+    }
+}
+
+/// Move the last entry or child of `left` to the front of `right`, rotating
+/// it through their separator
+fn rotate_right<K: Clone, V>(left: &mut Node<K, V>, separator: &mut K, right: &mut Node<K, V>) {
+    match (left, right) {
+        (Node::Leaf { entries: left }, Node::Leaf { entries: right }) => {
+            let entry = left.pop().expect("the sibling can spare an entry");
+            *separator = entry.0.clone();
+            right.insert(0, entry);
+        }
+        (
+            Node::Internal { keys: left_keys, children: left_children },
+            Node::Internal { keys: right_keys, children: right_children },
+        ) => {
+            let key = left_keys.pop().expect("the sibling can spare a key");
+            right_keys.insert(0, mem::replace(separator, key));
+            right_children.insert(0, left_children.pop().expect("one child per key, plus one"));
+        }
+        _ => unreachable!("siblings are at the same level"),
+    }
+}
+
+/// Move the first entry or child of `right` to the end of `left`, rotating
+/// it through their separator
+fn rotate_left<K: Clone, V>(left: &mut Node<K, V>, separator: &mut K, right: &mut Node<K, V>) {
+    match (left, right) {
+        (Node::Leaf { entries: left }, Node::Leaf { entries: right }) => {
+            left.push(right.remove(0));
+            *separator = right[0].0.clone();
+        }
+        (
+            Node::Internal { keys: left_keys, children: left_children },
+            Node::Internal { keys: right_keys, children: right_children },
+        ) => {
+            left_keys.push(mem::replace(separator, right_keys.remove(0)));
+            left_children.push(right_children.remove(0));
+        }
+        _ => unreachable!("siblings are at the same level"),
+    }
+}
+
+/// Join two adjacent siblings and the separator between them into one node
+fn merge<K, V>(left: Node<K, V>, separator: K, right: Node<K, V>) -> Node<K, V> {
+    match (left, right) {
+        (Node::Leaf { entries: mut left }, Node::Leaf { entries: right }) => {
+            left.extend(right);
+            Node::Leaf { entries: left }
+        }
+        (
+            Node::Internal { keys: mut left_keys, children: mut left_children },
+            Node::Internal { keys: right_keys, children: right_children },
+        ) => {
+            left_keys.push(separator);
+            left_keys.extend(right_keys);
+            left_children.extend(right_children);
+            Node::Internal {
+                keys: left_keys,
+                children: left_children,
+            }
+        }
+        _ => unreachable!("siblings are at the same level"),
+    }
+}
+
+/// Position of an entry: the child taken at each internal level on the way
+/// down, and an index into the leaf reached
+///
+/// A cursor past the last entry of a leaf moves on to the next leaf, so two
+/// cursors at the same entry always compare equal.
+struct Cursor<'a, K, V> {
+    path: Vec<(&'a [Child<K, V>], usize)>,
+    leaf: &'a [(K, V)],
+    pos: usize,
+}
+
+impl<'a, K, V> Cursor<'a, K, V> {
+    fn exhausted() -> Self {
+        Cursor {
+            path: Vec::new(),
+            leaf: &[],
+            pos: 0,
+        }
+    }
+
+    /// Cursor at the first entry whose key is not `before` the target, where
+    /// `before` holds for a prefix of the keys in order
+    fn seek(root: &'a Node<K, V>, before: impl Fn(&K) -> bool) -> Self {
+        let mut path = Vec::new();
+        let mut node = root;
+        loop {
+            match node {
+                Node::Internal { keys, children } => {
+                    let idx = keys.partition_point(&before);
+                    path.push((children.as_slice(), idx));
+                    node = &children[idx];
+                }
+                Node::Leaf { entries } => {
+                    let pos = entries.partition_point(|(k, _)| before(k));
+                    let mut cursor = Cursor { path, leaf: entries, pos };
+                    cursor.settle();
+                    return cursor;
+                }
+            }
+        }
+    }
+
+    /// Whether the cursor has run past the last entry of the tree
+    fn is_end(&self) -> bool {
+        self.pos == self.leaf.len()
+    }
+
+    /// Step off the end of the current leaf onto the first entry of the next
+    fn settle(&mut self) {
+        while self.is_end() {
+            let Some((children, idx)) = self.path.pop() else {
+                return;
+            };
+            if idx + 1 == children.len() {
+                continue;
+            }
+            self.path.push((children, idx + 1));
+            let mut node = &*children[idx + 1];
+            while let Node::Internal { children, .. } = node {
+                self.path.push((children, 0));
+                node = &children[0];
+            }
+            let Node::Leaf { entries } = node else {
+                unreachable!("the descent stops at a leaf");
+            };
+            self.leaf = entries;
+            self.pos = 0;
+        }
+    }
+}
+
+/// Iterator over the entries of a `PersistentBPlusTree` in ascending key order
+pub struct Iter<'a, K, V> {
+    front: Cursor<'a, K, V>,
+    /// Leaf and index the iteration stops at, or `None` to run to the end
+    end: Option<(&'a [(K, V)], usize)>,
+}
+
+impl<'a, K, V> Iterator for Iter<'a, K, V> {
+    type Item = (&'a K, &'a V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.front.is_end() {
+            return None;
+        }
+        if let Some((leaf, pos)) = self.end {
+            if std::ptr::eq(leaf, self.front.leaf) && pos == self.front.pos {
+                return None;
+            }
+        }
+        let leaf = self.front.leaf;
+        let (key, value) = &leaf[self.front.pos];
+        self.front.pos += 1;
+        self.front.settle();
+        Some((key, value))
+    }
+}
+
+impl<K, V> Clone for PersistentBPlusTree<K, V> {
+    /// Take a snapshot; O(1), as the clone shares every node
+    fn clone(&self) -> Self {
+        PersistentBPlusTree {
+            root: Arc::clone(&self.root),
+            len: self.len,
+            min_degree: self.min_degree,
+        }
+    }
+}
+
+impl<K: Ord + Clone, V: Clone> Default for PersistentBPlusTree<K, V> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<K: Ord + Clone, V: Clone> FromIterator<(K, V)> for PersistentBPlusTree<K, V> {
+    fn from_iter<I: IntoIterator<Item = (K, V)>>(iter: I) -> Self {
+        iter.into_iter()
+            .fold(Self::new(), |tree, (key, value)| tree.insert(key, value))
+    }
+}
+
+impl<K: Ord + Clone, V: Clone + PartialEq> PartialEq for PersistentBPlusTree<K, V> {
+    fn eq(&self, other: &Self) -> bool {
+        self.len == other.len && self.iter().eq(other.iter())
+    }
+}
+
+impl<K: Ord + Clone, V: Clone + Eq> Eq for PersistentBPlusTree<K, V> {}
+
+impl<K: Ord + Clone + fmt::Debug, V: Clone + fmt::Debug> fmt::Debug for PersistentBPlusTree<K, V> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_map().entries(self.iter()).finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeMap;
+
+    /// Check ordering, occupancy, uniform depth and separator bounds below
+    /// `node`, returning the leaf depth and the number of entries
+    fn check_node(
+        node: &Node<i32, i32>,
+        min_degree: usize,
+        is_root: bool,
+        lower: Option<i32>,
+        upper: Option<i32>,
+    ) -> (usize, usize) {
+        let in_bounds = |k: i32| lower.is_none_or(|l| l <= k) && upper.is_none_or(|u| k < u);
+        assert!(node.num_keys() < 2 * min_degree);
+        assert!(is_root || node.num_keys() + 1 >= min_degree);
+        match node {
+            Node::Leaf { entries } => {
+                assert!(entries.windows(2).all(|w| w[0].0 < w[1].0));
+                assert!(entries.iter().all(|(k, _)| in_bounds(*k)));
+                (1, entries.len())
+            }
+            Node::Internal { keys, children } => {
+                assert!(!keys.is_empty());
+                assert!(keys.windows(2).all(|w| w[0] < w[1]));
+                assert_eq!(children.len(), keys.len() + 1);
+                let mut depths = Vec::new();
+                let mut entries = 0;
+                for (i, child) in children.iter().enumerate() {
+                    let lo = if i == 0 { lower } else { Some(keys[i - 1]) };
+                    let hi = keys.get(i).copied().or(upper);
+                    let (depth, count) = check_node(child, min_degree, false, lo, hi);
+                    depths.push(depth);
+                    entries += count;
+                }
+                assert!(depths.windows(2).all(|w| w[0] == w[1]));
+                (depths[0] + 1, entries)
+            }
+        }
+    }
+
+    fn assert_matches(tree: &PersistentBPlusTree<i32, i32>, model: &BTreeMap<i32, i32>) {
+        let (_, entries) = check_node(&tree.root, tree.min_degree, true, None, None);
+        assert_eq!(entries, tree.len());
+        assert_eq!(tree.len(), model.len());
+        assert!(tree.iter().eq(model.iter()));
+        assert!(tree.range(100..200).eq(model.range(100..200)));
+    }
+
+    #[test]
+    fn test_versions_are_independent() {
+        let empty = PersistentBPlusTree::with_degree(2);
+        let one = empty.insert(1, "one".to_string());
+        let two = one.insert(2, "two".to_string());
+        let replaced = two.insert(1, "uno".to_string());
+        let removed = replaced.remove(&2);
+
+        assert!(empty.is_empty());
+        assert_eq!(one.get(&1).map(String::as_str), Some("one"));
+        assert!(!one.contains_key(&2));
+        assert_eq!(two.len(), 2);
+        assert_eq!(replaced.get(&1).map(String::as_str), Some("uno"));
+        assert_eq!(two.get(&1).map(String::as_str), Some("one"));
+        assert_eq!(format!("{:?}", removed), r#"{1: "uno"}"#);
+        assert_eq!(removed.remove(&7), removed);
+    }
+
+    #[test]
+    fn test_updates_share_untouched_nodes() {
+        let tree: PersistentBPlusTree<i32, i32> = (0..1000).map(|i| (i * 2, i)).collect();
+        let updated = tree.insert(1001, -1);
+        let (Node::Internal { children: before, .. }, Node::Internal { children: after, .. }) =
+            (&*tree.root, &*updated.root)
+        else {
+            panic!("a thousand entries need more than one leaf");
+        };
+        assert_eq!(before.len(), after.len());
+        let shared = before.iter().zip(after).filter(|(a, b)| Arc::ptr_eq(a, b)).count();
+        assert_eq!(shared, before.len() - 1);
+
+        let unchanged = tree.remove(&1);
+        assert!(Arc::ptr_eq(&tree.root, &unchanged.root));
+        assert!(Arc::ptr_eq(&tree.clone().root, &tree.root));
+    }
+
+    #[test]
+    fn test_range() {
+        let tree: PersistentBPlusTree<i32, i32> = (0..50).map(|i| (i * 10, i)).collect();
+        let keys = |iter: Iter<'_, i32, i32>| iter.map(|(k, _)| *k).collect::<Vec<_>>();
+        assert_eq!(keys(tree.range(95..=130)), [100, 110, 120, 130]);
+        assert_eq!(keys(tree.range((Bound::Excluded(100), Bound::Excluded(130)))), [110, 120]);
+        assert_eq!(keys(tree.range(480..)), [480, 490]);
+        assert_eq!(tree.range(..).count(), 50);
+        assert!(keys(tree.range(101..109)).is_empty());
+        assert!(keys(tree.range(500..)).is_empty());
+        assert!(keys(tree.range((Bound::Included(30), Bound::Excluded(30)))).is_empty());
+
+        let names: PersistentBPlusTree<String, ()> =
+            ["b", "a", "c"].iter().map(|s| (s.to_string(), ())).collect();
+        assert_eq!(names.range::<str, _>((Bound::Included("b"), Bound::Unbounded)).count(), 2);
+        assert!(names.contains_key("a"));
+    }
+
+    #[test]
+    fn test_random_operations_keep_every_version() {
+        let mut seed = 0x2545_F491_4F6C_DD1Du64;
+        let mut next = move || {
+            seed ^= seed << 13;
+            seed ^= seed >> 7;
+            seed ^= seed << 17;
+            seed
+        };
+
+        let mut versions = vec![(PersistentBPlusTree::with_degree(2), BTreeMap::new())];
+        for step in 0..3000 {
+            let (tree, model) = versions.last().unwrap();
+            let key = (next() % 400) as i32;
+            let (tree, mut model) = (tree.clone(), model.clone());
+            let tree = if next() % 3 == 0 {
+                model.remove(&key);
+                tree.remove(&key)
+            } else {
+                model.insert(key, step);
+                tree.insert(key, step)
+            };
+            assert_matches(&tree, &model);
+            versions.push((tree, model));
+        }
+        for (tree, model) in versions.iter().step_by(97) {
+            assert_matches(tree, model);
+        }
+
+        let (mut tree, mut model) = versions.pop().unwrap();
+        while let Some(&key) = model.keys().nth(next() as usize % model.len().max(1)) {
+            model.remove(&key);
+            tree = tree.remove(&key);
+            assert_matches(&tree, &model);
+        }
+        assert!(matches!(&*tree.root, Node::Leaf { entries } if entries.is_empty()));
+    }
+}
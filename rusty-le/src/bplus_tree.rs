@@ -1,30 +1,60 @@
+use crate::profile::{self, Phase};
+use rand_core::RngCore;
+use std::borrow::Borrow;
+use std::cmp::Ordering;
+use std::collections::BTreeSet;
 use std::fmt;
+use std::iter::Peekable;
+use std::ops::{Bound, RangeBounds};
+use std::vec;
 
-const MIN_DEGREE: usize = 3;
+/// Minimum degree used by `BPlusTree::new`
+pub(crate) const DEFAULT_MIN_DEGREE: usize = 3;
+
+/// Index of a node in the tree's node arena
+pub type NodeId = usize;
+
+/// Tree over raw byte keys and values, ordered lexicographically by byte
+///
+/// Lookups accept `&[u8]`, so serialized keys such as UUIDs or hashes need no
+/// `String` conversion or UTF-8 validation.
+pub type BytesTree = BPlusTree<Vec<u8>, Vec<u8>>;
 
 /// B+ Tree Entry with key and value
 #[derive(Clone, Debug)]
-pub struct Entry {
-    pub key: i32,
-    pub value: String,
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Entry<K, V> {
+    pub key: K,
+    pub value: V,
 }
 
 /// B+ Tree Node - either Leaf or Internal
+///
+/// Leaves are chained to their neighbours through `prev`/`next` so that
+/// ordered scans can walk the leaf level without revisiting internal nodes.
+/// Internal nodes record how many entries sit beneath each child, which lets
+/// order-statistic queries descend without scanning.
 #[derive(Clone, Debug)]
-pub enum Node {
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Node<K, V> {
     Leaf {
-        entries: Vec<Entry>,
+        entries: Vec<Entry<K, V>>,
+        prev: Option<NodeId>,
+        next: Option<NodeId>,
     },
     Internal {
-        keys: Vec<i32>,
-        children: Vec<Box<Node>>,
+        keys: Vec<K>,
+        children: Vec<NodeId>,
+        counts: Vec<usize>,
     },
 }
 
-impl Node {
+impl<K, V> Node<K, V> {
     pub fn new_leaf() -> Self {
         Node::Leaf {
             entries: Vec::new(),
+            prev: None,
+            next: None,
         }
     }
 
@@ -32,6 +62,7 @@ impl Node {
         Node::Internal {
             keys: Vec::new(),
             children: Vec::new(),
+            counts: Vec::new(),
         }
     }
 
@@ -41,323 +72,3491 @@ impl Node {
 
     pub fn num_keys(&self) -> usize {
         match self {
-            Node::Leaf { entries } => entries.len(),
+            Node::Leaf { entries, .. } => entries.len(),
             Node::Internal { keys, .. } => keys.len(),
         }
     }
 
-    pub fn is_full(&self) -> bool {
-        self.num_keys() >= 2 * MIN_DEGREE - 1
+    pub fn is_full(&self, min_degree: usize) -> bool {
+        self.num_keys() >= 2 * min_degree - 1
+    }
+
+    /// True when a non-root node holds fewer than the minimum number of keys
+    pub fn is_underflow(&self, min_degree: usize) -> bool {
+        self.num_keys() < min_degree - 1
+    }
+
+    fn can_lend(&self, min_degree: usize) -> bool {
+        self.num_keys() > min_degree - 1
+    }
+}
+
+/// Ordering used to arrange a tree's keys
+///
+/// Implemented for `NaturalOrder` and for any `Fn(&T, &T) -> Ordering`
+/// closure. Lookups by a borrowed form `Q` of the key need a comparator for
+/// `Q` as well, which `NaturalOrder` provides for every `Q: Ord`.
+pub trait Comparator<T: ?Sized> {
+    fn compare(&self, a: &T, b: &T) -> Ordering;
+}
+
+/// Orders keys by their own `Ord` implementation; the default comparator
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct NaturalOrder;
+
+impl<T: Ord + ?Sized> Comparator<T> for NaturalOrder {
+    fn compare(&self, a: &T, b: &T) -> Ordering {
+        a.cmp(b)
+    }
+}
+
+/// Orders keys by their `Ord` implementation reversed, largest first
+///
+/// Range bounds follow the same order, so the start bound is the larger key.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Descending;
+
+impl<T: Ord + ?Sized> Comparator<T> for Descending {
+    fn compare(&self, a: &T, b: &T) -> Ordering {
+        b.cmp(a)
+    }
+}
+
+impl<T: ?Sized, F: Fn(&T, &T) -> Ordering> Comparator<T> for F {
+    fn compare(&self, a: &T, b: &T) -> Ordering {
+        self(a, b)
     }
 }
 
 /// B+ Tree Implementation
-pub struct BPlusTree {
-    root: Box<Node>,
+///
+/// Generic over any key type and the comparator that orders it; the defaults
+/// keep the original `i32 -> String` mapping in natural order. Nodes live in
+/// an arena and refer to each other by `NodeId`; slots freed by merges are
+/// recycled through `free`.
+#[derive(Clone)]
+pub struct BPlusTree<K = i32, V = String, C = NaturalOrder> {
+    nodes: Vec<Node<K, V>>,
+    free: Vec<NodeId>,
+    root: NodeId,
     height: usize,
+    len: usize,
+    min_degree: usize,
+    cmp: C,
 }
 
-impl BPlusTree {
+impl<K: Ord + Clone, V> BPlusTree<K, V> {
     /// Create a new empty B+ Tree
     pub fn new() -> Self {
+        Self::with_degree(DEFAULT_MIN_DEGREE)
+    }
+
+    /// Create a new empty B+ Tree whose non-root nodes hold between
+    /// `min_degree - 1` and `2 * min_degree - 1` keys
+    ///
+    /// Larger degrees mean wider, shallower trees; tune it to the key size and
+    /// cache line.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `min_degree` is less than 2.
+    pub fn with_degree(min_degree: usize) -> Self {
+        Self::with_degree_and_comparator(min_degree, NaturalOrder)
+    }
+}
+
+impl<K: Ord + Clone, V> BPlusTree<K, V, Descending> {
+    /// Create a new empty B+ Tree that keeps keys in descending order, so
+    /// `iter()` and `range()` yield the largest (e.g. newest) keys first
+    pub fn descending() -> Self {
+        Self::with_comparator(Descending)
+    }
+}
+
+impl<K: Clone, V, C: Comparator<K>> BPlusTree<K, V, C> {
+    /// Create a new empty B+ Tree that orders keys by `cmp` instead of their
+    /// `Ord` implementation
+    ///
+    /// Keys comparing `Equal` under `cmp` are treated as the same key.
+    ///
+    /// ```
+    /// use rusty_le::bplus_tree::BPlusTree;
+    ///
+    /// let mut newest_first = BPlusTree::with_comparator(|a: &u64, b: &u64| b.cmp(a));
+    /// newest_first.insert(1, "old");
+    /// newest_first.insert(2, "new");
+    /// assert_eq!(newest_first.keys().next(), Some(&2));
+    /// ```
+    pub fn with_comparator(cmp: C) -> Self {
+        Self::with_degree_and_comparator(DEFAULT_MIN_DEGREE, cmp)
+    }
+
+    /// Create a new empty B+ Tree with both a minimum degree and a comparator
+    ///
+    /// # Panics
+    ///
+    /// Panics if `min_degree` is less than 2.
+    pub fn with_degree_and_comparator(min_degree: usize, cmp: C) -> Self {
+        assert!(min_degree >= 2, "min_degree must be at least 2, got {}", min_degree);
         BPlusTree {
-            root: Box::new(Node::new_leaf()),
+            nodes: vec![Node::new_leaf()],
+            free: Vec::new(),
+            root: 0,
             height: 1,
+            len: 0,
+            min_degree,
+            cmp,
+        }
+    }
+
+    /// The comparator ordering this tree's keys
+    pub fn comparator(&self) -> &C {
+        &self.cmp
+    }
+
+    /// The minimum degree this tree was created with
+    pub fn min_degree(&self) -> usize {
+        self.min_degree
+    }
+
+    /// Number of entries in the tree
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Whether the tree holds no entries
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    fn alloc(&mut self, node: Node<K, V>) -> NodeId {
+        match self.free.pop() {
+            Some(id) => {
+                self.nodes[id] = node;
+                id
+            }
+            None => {
+                self.nodes.push(node);
+                self.nodes.len() - 1
+            }
+        }
+    }
+
+    fn release(&mut self, id: NodeId) -> Node<K, V> {
+        self.free.push(id);
+        std::mem::replace(&mut self.nodes[id], Node::new_leaf())
+    }
+
+    fn children(&self, id: NodeId) -> &[NodeId] {
+        match &self.nodes[id] {
+            Node::Internal { children, .. } => children,
+            Node::Leaf { .. } => &[],
+        }
+    }
+
+    /// Number of entries in the subtree rooted at `id`
+    fn subtree_len(&self, id: NodeId) -> usize {
+        match &self.nodes[id] {
+            Node::Leaf { entries, .. } => entries.len(),
+            Node::Internal { counts, .. } => counts.iter().sum(),
+        }
+    }
+
+    /// Refresh an internal node's per-child entry counts from its children
+    fn recount(&mut self, id: NodeId) {
+        let counts = self.children(id).iter().map(|&c| self.subtree_len(c)).collect();
+        if let Node::Internal { counts: slot, .. } = &mut self.nodes[id] {
+            *slot = counts;
         }
     }
 
+    fn alloc_internal(&mut self, keys: Vec<K>, children: Vec<NodeId>) -> NodeId {
+        let id = self.alloc(Node::Internal {
+            keys,
+            children,
+            counts: Vec::new(),
+        });
+        self.recount(id);
+        id
+    }
+
     /// Insert a key-value pair
-    pub fn insert(&mut self, key: i32, value: String) {
-        if self.root.is_full() {
-            let old_root = std::mem::replace(&mut self.root, Box::new(Node::new_internal()));
-            
-            if let Node::Internal {
-                keys: _,
-                ref mut children,
-            } = *self.root
-            {
-                children.push(old_root);
-                self.split_child(0);
-            }
-            
+    ///
+    /// Full nodes are split on the way down, so the leaf that receives the
+    /// entry always has room and no split ever has to propagate back up.
+    pub fn insert(&mut self, key: K, value: V) {
+        self.insert_entry(key, value);
+    }
+
+    /// Insert a key-value pair, returning the leaf and index it was stored at
+    fn insert_entry(&mut self, key: K, value: V) -> (NodeId, usize) {
+        if self.nodes[self.root].is_full(self.min_degree) {
+            let old_root = self.root;
+            self.root = self.alloc_internal(Vec::new(), vec![old_root]);
+            self.split_child(self.root, 0);
+
             self.height += 1;
         }
 
-        self.insert_non_full(key, value);
+        let (leaf, idx, added) = self.insert_non_full(self.root, key, value);
+        if added {
+            self.len += 1;
+        }
+        (leaf, idx)
     }
 
-    fn insert_non_full(&mut self, key: i32, value: String) {
-        if self.root.is_leaf() {
-            if let Node::Leaf { ref mut entries } = *self.root {
-                if let Some(pos) = entries.iter().position(|e| e.key == key) {
-                    entries[pos].value = value;
-                } else {
-                    let pos = entries.iter().position(|e| e.key > key).unwrap_or(entries.len());
-                    entries.insert(pos, Entry { key, value });
-                }
+    /// Insert many pairs at once
+    ///
+    /// The batch is sorted (the last value wins for repeated keys) and applied
+    /// in a single pass. Each subtree the batch touches is entered once with all
+    /// the pairs that belong under it, each leaf merges its pairs in one sweep,
+    /// and nodes that overflow are split into as many siblings as needed on the
+    /// way back up. A batch of m pairs into a tree of n entries costs
+    /// O(m log m) to sort plus at most one O(log n) descent per pair. Pairs
+    /// bound for the same leaf share a single descent, so clustered or
+    /// sequential keys cost far less than separate inserts, while keys spread
+    /// thinly across a large tree cost about the same.
+    pub fn insert_batch(&mut self, batch: Vec<(K, V)>) {
+        let mut batch: Vec<Entry<K, V>> = batch
+            .into_iter()
+            .map(|(key, value)| Entry { key, value })
+            .collect();
+        batch.sort_by(|a, b| self.cmp.compare(&a.key, &b.key));
+        batch.dedup_by(|later, earlier| {
+            let duplicate = self.cmp.compare(&later.key, &earlier.key).is_eq();
+            if duplicate {
+                std::mem::swap(&mut later.value, &mut earlier.value);
             }
-        } else {
-            let mut child_idx = 0;
-            {
-                if let Node::Internal { keys, .. } = self.root.as_ref() {
-                    for (i, k) in keys.iter().enumerate() {
-                        if key < *k {
-                            child_idx = i;
-                            break;
-                        }
-                        child_idx = i + 1;
+            duplicate
+        });
+
+        let mut pending = batch.into_iter().peekable();
+        let mut siblings = self.insert_sorted(self.root, &mut pending, None);
+        while !siblings.is_empty() {
+            let (keys, mut children): (Vec<K>, Vec<NodeId>) = siblings.into_iter().unzip();
+            children.insert(0, self.root);
+            self.root = self.alloc_internal(keys, children);
+            self.height += 1;
+            siblings = self.split_overflowing(self.root);
+        }
+    }
+
+    /// Merge every pending pair that sorts before `upper` into the subtree at
+    /// `node`, returning the separators and ids of the new right siblings the
+    /// node had to be split into
+    fn insert_sorted(
+        &mut self,
+        node: NodeId,
+        pending: &mut Peekable<vec::IntoIter<Entry<K, V>>>,
+        upper: Option<&K>,
+    ) -> Vec<(K, NodeId)> {
+        let below = |cmp: &C, entry: &Entry<K, V>| {
+            upper.is_none_or(|upper| cmp.compare(&entry.key, upper).is_lt())
+        };
+
+        if let Node::Leaf { entries, .. } = &mut self.nodes[node] {
+            let _scope = profile::scope(Phase::LeafSearch);
+            // The batch is sorted, so each search resumes after the last slot
+            let mut from = 0;
+            while let Some(entry) = pending.next_if(|entry| below(&self.cmp, entry)) {
+                let found =
+                    entries[from..].binary_search_by(|e| self.cmp.compare(&e.key, &entry.key));
+                match found {
+                    Ok(pos) => entries[from + pos].value = entry.value,
+                    Err(pos) => {
+                        entries.insert(from + pos, entry);
+                        self.len += 1;
                     }
                 }
+                from += found.unwrap_or_else(|pos| pos) + 1;
             }
-
-            let should_split = if let Node::Internal { children, .. } = self.root.as_ref() {
-                children[child_idx].is_full()
-            } else {
-                false
-            };
-
-            if should_split {
-                self.split_child_internal(child_idx);
-                let key_val = if let Node::Internal { keys, .. } = self.root.as_ref() {
-                    keys[child_idx]
-                } else {
-                    i32::MIN
+        } else {
+            while let Some(next) = pending.peek().filter(|entry| below(&self.cmp, entry)) {
+                let (idx, child, child_upper) = {
+                    let _scope = profile::scope(Phase::Descend);
+                    let Node::Internal { keys, children, .. } = &self.nodes[node] else {
+                        unreachable!("checked above");
+                    };
+                    let idx = keys.partition_point(|k| self.cmp.compare(k, &next.key).is_le());
+                    (idx, children[idx], keys.get(idx).cloned())
                 };
-                if key > key_val {
-                    child_idx += 1;
+                let siblings = self.insert_sorted(child, pending, child_upper.as_ref().or(upper));
+
+                let child_len = self.subtree_len(child);
+                let sibling_lens: Vec<usize> =
+                    siblings.iter().map(|&(_, id)| self.subtree_len(id)).collect();
+                if let Node::Internal { keys, children, counts } = &mut self.nodes[node] {
+                    counts[idx] = child_len;
+                    if !siblings.is_empty() {
+                        counts.splice(idx + 1..idx + 1, sibling_lens);
+                        let (new_keys, new_children): (Vec<K>, Vec<NodeId>) =
+                            siblings.into_iter().unzip();
+                        keys.splice(idx..idx, new_keys);
+                        children.splice(idx + 1..idx + 1, new_children);
+                    }
                 }
             }
-
-            self.insert_into_child(child_idx, key, value);
         }
+        self.split_overflowing(node)
     }
 
-    fn insert_into_child(&mut self, child_idx: usize, key: i32, value: String) {
-        if let Node::Internal { ref mut children, .. } = *self.root {
-            if children[child_idx].is_leaf() {
-                if let Node::Leaf { ref mut entries } = *children[child_idx] {
-                    if let Some(pos) = entries.iter().position(|e| e.key == key) {
-                        entries[pos].value = value;
+    /// Split a node holding more than the maximum number of keys into the
+    /// fewest evenly sized siblings that fit, keeping the first part in place
+    /// and returning each new sibling's separator and id
+    fn split_overflowing(&mut self, node: NodeId) -> Vec<(K, NodeId)> {
+        if self.nodes[node].num_keys() < 2 * self.min_degree {
+            return Vec::new();
+        }
+        let _scope = profile::scope(Phase::Split);
+        match std::mem::replace(&mut self.nodes[node], Node::new_leaf()) {
+            Node::Leaf { entries, prev, next } => {
+                let mut parts = even_chunks(entries, 2 * self.min_degree - 1).into_iter();
+                self.nodes[node] = Node::Leaf {
+                    entries: parts.next().expect("an overflowing leaf is not empty"),
+                    prev,
+                    next: None,
+                };
+                let mut siblings = Vec::new();
+                let mut last = node;
+                for part in parts {
+                    let key = part[0].key.clone();
+                    let id = self.alloc(Node::Leaf {
+                        entries: part,
+                        prev: Some(last),
+                        next: None,
+                    });
+                    if let Node::Leaf { next, .. } = &mut self.nodes[last] {
+                        *next = Some(id);
+                    }
+                    siblings.push((key, id));
+                    last = id;
+                }
+                if let Node::Leaf { next: slot, .. } = &mut self.nodes[last] {
+                    *slot = next;
+                }
+                if let Some(Node::Leaf { prev, .. }) = next.map(|id| &mut self.nodes[id]) {
+                    *prev = Some(last);
+                }
+                siblings
+            }
+            Node::Internal { keys, children, counts } => {
+                // Pair every child with the separator to its left
+                let items: Vec<(Option<K>, NodeId, usize)> = std::iter::once(None)
+                    .chain(keys.into_iter().map(Some))
+                    .zip(children)
+                    .zip(counts)
+                    .map(|((key, child), count)| (key, child, count))
+                    .collect();
+                let mut siblings = Vec::new();
+                for (i, part) in even_chunks(items, 2 * self.min_degree).into_iter().enumerate() {
+                    let mut part = part.into_iter();
+                    let (separator, first, first_count) =
+                        part.next().expect("chunks are non-empty");
+                    let mut keys = Vec::new();
+                    let mut children = vec![first];
+                    let mut counts = vec![first_count];
+                    for (key, child, count) in part {
+                        keys.push(key.expect("only the first child lacks a separator"));
+                        children.push(child);
+                        counts.push(count);
+                    }
+                    let part = Node::Internal { keys, children, counts };
+                    if i == 0 {
+                        self.nodes[node] = part;
                     } else {
-                        let pos = entries.iter().position(|e| e.key > key).unwrap_or(entries.len());
-                        entries.insert(pos, Entry { key, value });
+                        let id = self.alloc(part);
+                        let separator = separator.expect("later parts start after a separator");
+                        siblings.push((separator, id));
                     }
                 }
+                siblings
             }
         }
     }
 
-    fn split_child(&mut self, child_idx: usize) {
-        if let Node::Internal {
-            ref mut keys,
-            ref mut children,
-        } = *self.root
-        {
-            let mid = MIN_DEGREE - 1;
-            let child = children[child_idx].clone();
+    /// Insert `value` under `key`, or fold it into the existing value with
+    /// `merge(existing, value)` instead of overwriting
+    ///
+    /// Useful for accumulating, e.g. summing counters or appending to lists.
+    pub fn insert_with<F: FnOnce(&mut V, V)>(&mut self, key: K, value: V, merge: F) -> Upsert {
+        match self.entry(key) {
+            TreeEntry::Occupied(mut entry) => {
+                merge(entry.get_mut(), value);
+                Upsert::Merged
+            }
+            TreeEntry::Vacant(entry) => {
+                entry.insert(value);
+                Upsert::Created
+            }
+        }
+    }
 
-            if let Node::Leaf { ref entries } = child.as_ref() {
-                if entries.len() > mid {
-                    let split_key = entries[mid].key;
-                    
-                    let left_entries = entries[..mid].to_vec();
-                    let right_entries = entries[mid..].to_vec();
+    /// Insert into the subtree rooted at `node`, which must not be full
+    ///
+    /// Returns the leaf and index holding the entry, and whether it was added
+    /// rather than an existing one updated
+    fn insert_non_full(&mut self, node: NodeId, key: K, value: V) -> (NodeId, usize, bool) {
+        let mut child_idx = match &self.nodes[node] {
+            Node::Leaf { .. } => {
+                let _scope = profile::scope(Phase::LeafSearch);
+                let (idx, added) =
+                    Self::insert_into_leaf(&self.cmp, &mut self.nodes[node], key, value);
+                return (node, idx, added);
+            }
+            Node::Internal { keys, .. } => {
+                let _scope = profile::scope(Phase::Descend);
+                keys.partition_point(|k| self.cmp.compare(k, &key).is_le())
+            }
+        };
 
-                    children[child_idx] = Box::new(Node::Leaf {
-                        entries: left_entries,
-                    });
+        let child = self.children(node)[child_idx];
+        if self.nodes[child].is_full(self.min_degree) {
+            self.split_child(node, child_idx);
+            if let Node::Internal { keys, .. } = &self.nodes[node] {
+                // Keys equal to the separator live in the right half
+                if self.cmp.compare(&key, &keys[child_idx]).is_ge() {
+                    child_idx += 1;
+                }
+            }
+        }
 
-                    let right_child = Box::new(Node::Leaf {
-                        entries: right_entries,
-                    });
+        let child = self.children(node)[child_idx];
+        let (leaf, idx, added) = self.insert_non_full(child, key, value);
+        if added {
+            if let Node::Internal { counts, .. } = &mut self.nodes[node] {
+                counts[child_idx] += 1;
+            }
+        }
+        (leaf, idx, added)
+    }
 
-                    keys.insert(child_idx, split_key);
-                    children.insert(child_idx + 1, right_child);
-                }
+    /// Store the pair in a leaf with room, returning its index and whether
+    /// the key was new
+    fn insert_into_leaf(cmp: &C, node: &mut Node<K, V>, key: K, value: V) -> (usize, bool) {
+        let Node::Leaf { entries, .. } = node else {
+            unreachable!("entries live in leaves");
+        };
+        match entries.binary_search_by(|e| cmp.compare(&e.key, &key)) {
+            Ok(pos) => {
+                entries[pos].value = value;
+                (pos, false)
+            }
+            Err(pos) => {
+                entries.insert(pos, Entry { key, value });
+                (pos, true)
             }
         }
     }
 
-    fn split_child_internal(&mut self, child_idx: usize) {
-        if let Node::Internal {
-            ref mut keys,
-            ref mut children,
-        } = *self.root
-        {
-            let mid = MIN_DEGREE - 1;
-            let child = children[child_idx].clone();
+    /// Split the full child at `child_idx` of `parent`, hanging the new right
+    /// half and its separator off `parent`
+    fn split_child(&mut self, parent: NodeId, child_idx: usize) {
+        let child = self.children(parent)[child_idx];
+        let split = if self.nodes[child].is_leaf() {
+            self.split_leaf(child)
+        } else {
+            Some(self.halve_internal(child))
+        };
+        if let Some((split_key, right_child)) = split {
+            let moved = self.subtree_len(right_child);
+            if let Node::Internal { keys, children, counts } = &mut self.nodes[parent] {
+                keys.insert(child_idx, split_key);
+                children.insert(child_idx + 1, right_child);
+                counts[child_idx] -= moved;
+                counts.insert(child_idx + 1, moved);
+            }
+        }
+    }
 
-            if let Node::Leaf { ref entries } = child.as_ref() {
-                if entries.len() > mid {
-                    let split_key = entries[mid].key;
-                    
-                    let left_entries = entries[..mid].to_vec();
-                    let right_entries = entries[mid..].to_vec();
+    /// Move the upper half of a leaf into a new right sibling, linking it into
+    /// the leaf chain, and return the separator key with the new leaf's id
+    fn split_leaf(&mut self, leaf: NodeId) -> Option<(K, NodeId)> {
+        let _scope = profile::scope(Phase::Split);
+        let mid = self.min_degree - 1;
+        let (right_entries, old_next) = match &mut self.nodes[leaf] {
+            Node::Leaf { entries, next, .. } if entries.len() > mid => {
+                (entries.split_off(mid), *next)
+            }
+            _ => return None,
+        };
+        let split_key = right_entries[0].key.clone();
 
-                    children[child_idx] = Box::new(Node::Leaf {
-                        entries: left_entries,
-                    });
+        let right_child = self.alloc(Node::Leaf {
+            entries: right_entries,
+            prev: Some(leaf),
+            next: old_next,
+        });
+        if let Node::Leaf { next, .. } = &mut self.nodes[leaf] {
+            *next = Some(right_child);
+        }
+        if let Some(Node::Leaf { prev, .. }) = old_next.map(|id| &mut self.nodes[id]) {
+            *prev = Some(right_child);
+        }
 
-                    let right_child = Box::new(Node::Leaf {
-                        entries: right_entries,
-                    });
+        Some((split_key, right_child))
+    }
 
-                    keys.insert(child_idx, split_key);
-                    children.insert(child_idx + 1, right_child);
-                }
+    /// Remove a key, returning its value if it was present
+    pub fn remove<Q>(&mut self, key: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized,
+        C: Comparator<Q>,
+    {
+        let removed = self.remove_from(self.root, key)?;
+        self.len -= 1;
+
+        self.collapse_root();
+        Some(removed)
+    }
+
+    /// Collapse a root that was left with a single child after a merge
+    fn collapse_root(&mut self) {
+        while let Node::Internal { children, .. } = &self.nodes[self.root] {
+            if children.len() > 1 {
+                break;
             }
+            let child = children[0];
+            self.release(self.root);
+            self.root = child;
+            self.height -= 1;
         }
     }
 
-    /// Search for a value by key
-    pub fn search(&self, key: i32) -> Option<String> {
-        self.search_recursive(&self.root, key)
+    /// Remove and return the smallest entry
+    pub fn pop_first(&mut self) -> Option<(K, V)> {
+        let key = self.first_key_value()?.0.clone();
+        self.remove(&key).map(|value| (key, value))
     }
 
-    fn search_recursive(&self, node: &Node, key: i32) -> Option<String> {
-        match node {
-            Node::Leaf { entries } => {
-                entries
-                    .iter()
-                    .find(|e| e.key == key)
-                    .map(|e| e.value.clone())
-            }
-            Node::Internal { keys, children } => {
-                let mut child_idx = 0;
-                for (i, k) in keys.iter().enumerate() {
-                    if key < *k {
-                        child_idx = i;
-                        break;
-                    }
-                    child_idx = i + 1;
-                }
-                self.search_recursive(&children[child_idx], key)
-            }
+    /// Remove and return the largest entry
+    pub fn pop_last(&mut self) -> Option<(K, V)> {
+        let key = self.last_key_value()?.0.clone();
+        self.remove(&key).map(|value| (key, value))
+    }
+
+    /// Remove every entry
+    ///
+    /// Every node is dropped along with its key, entry and child buffers; only
+    /// the node arena keeps its capacity, so refilling the tree reuses its slots.
+    pub fn clear(&mut self) {
+        self.nodes.clear();
+        self.nodes.push(Node::new_leaf());
+        self.free.clear();
+        self.root = 0;
+        self.height = 1;
+        self.len = 0;
+    }
+
+    /// Keep only the entries for which `f` returns true
+    ///
+    /// Filters each leaf in one pass over the leaf chain, then rebuilds the
+    /// tree bottom-up if anything was removed rather than rebalancing per key.
+    pub fn retain<F: FnMut(&K, &mut V) -> bool>(&mut self, mut f: F) {
+        let mut kept = 0;
+        let mut leaf = Some(self.leftmost_leaf());
+        while let Some(Node::Leaf { entries, next, .. }) = leaf.map(|id| &mut self.nodes[id]) {
+            entries.retain_mut(|e| f(&e.key, &mut e.value));
+            kept += entries.len();
+            leaf = *next;
+        }
+        if kept < self.len {
+            let entries = self.take_entries();
+            self.rebuild(entries);
         }
     }
 
-    /// Range query: find all entries in range [start, end]
-    pub fn range_query(&self, start: i32, end: i32) -> Vec<(i32, String)> {
-        let mut result = Vec::new();
-        self.range_query_recursive(&self.root, start, end, &mut result);
-        result
+    /// Remove every entry whose key falls within `range`, returning how many
+    /// were removed
+    ///
+    /// Subtrees lying entirely inside the range are released whole; only the
+    /// two boundary paths are visited key by key and rebalanced afterwards.
+    pub fn delete_range<T, R>(&mut self, range: R) -> usize
+    where
+        K: Borrow<T>,
+        T: ?Sized,
+        C: Comparator<T>,
+        R: RangeBounds<T>,
+    {
+        self.take_range(range).len()
     }
 
-    fn range_query_recursive(
-        &self,
-        node: &Node,
-        start: i32,
-        end: i32,
-        result: &mut Vec<(i32, String)>,
-    ) {
-        match node {
-            Node::Leaf { entries } => {
-                for entry in entries {
-                    if entry.key >= start && entry.key <= end {
-                        result.push((entry.key, entry.value.clone()));
+    /// Remove every entry and return them as an owning iterator in ascending
+    /// key order
+    pub fn drain(&mut self) -> Drain<K, V> {
+        let entries = self.take_entries();
+        self.clear();
+        Drain(entries.into_iter())
+    }
+
+    /// Remove the entries whose keys fall within `range` and return them as an
+    /// owning iterator in ascending key order
+    ///
+    /// The entries leave the tree immediately, even if the iterator is dropped
+    /// before being consumed.
+    pub fn drain_range<T, R>(&mut self, range: R) -> Drain<K, V>
+    where
+        K: Borrow<T>,
+        T: ?Sized,
+        C: Comparator<T>,
+        R: RangeBounds<T>,
+    {
+        Drain(self.take_range(range).into_iter())
+    }
+
+    /// Cut the entries within `range` out of the tree, in ascending key order
+    fn take_range<T, R>(&mut self, range: R) -> Vec<Entry<K, V>>
+    where
+        K: Borrow<T>,
+        T: ?Sized,
+        C: Comparator<T>,
+        R: RangeBounds<T>,
+    {
+        let mut removed = Vec::new();
+        if is_empty_range(&self.cmp, &range) {
+            return removed;
+        }
+        self.take_range_from(self.root, &range, &mut removed);
+        self.len -= removed.len();
+        self.collapse_root();
+        removed
+    }
+
+    fn take_range_from<T, R>(&mut self, node: NodeId, range: &R, out: &mut Vec<Entry<K, V>>)
+    where
+        K: Borrow<T>,
+        T: ?Sized,
+        C: Comparator<T>,
+        R: RangeBounds<T>,
+    {
+        let cmp = &self.cmp;
+        let (first, last) = match &mut self.nodes[node] {
+            Node::Leaf { entries, .. } => {
+                let lo = match range.start_bound() {
+                    Bound::Included(s) => {
+                        entries.partition_point(|e| cmp.compare(e.key.borrow(), s).is_lt())
                     }
-                }
+                    Bound::Excluded(s) => {
+                        entries.partition_point(|e| cmp.compare(e.key.borrow(), s).is_le())
+                    }
+                    Bound::Unbounded => 0,
+                };
+                let hi = match range.end_bound() {
+                    Bound::Included(e) => {
+                        entries.partition_point(|x| cmp.compare(x.key.borrow(), e).is_le())
+                    }
+                    Bound::Excluded(e) => {
+                        entries.partition_point(|x| cmp.compare(x.key.borrow(), e).is_lt())
+                    }
+                    Bound::Unbounded => entries.len(),
+                };
+                out.extend(entries.drain(lo..hi));
+                return;
             }
-            Node::Internal { keys, children } => {
-                for (i, key) in keys.iter().enumerate() {
-                    if start <= *key {
-                        self.range_query_recursive(&children[i], start, end, result);
+            Node::Internal { keys, children, .. } => {
+                let first = match range.start_bound() {
+                    Bound::Included(s) | Bound::Excluded(s) => {
+                        keys.partition_point(|k| cmp.compare(k.borrow(), s).is_le())
                     }
-                }
-                if let Some(last_child) = children.last() {
-                    if end > keys.last().copied().unwrap_or(i32::MIN) {
-                        self.range_query_recursive(last_child, start, end, result);
+                    Bound::Unbounded => 0,
+                };
+                let last = match range.end_bound() {
+                    Bound::Included(e) => {
+                        keys.partition_point(|k| cmp.compare(k.borrow(), e).is_le())
                     }
-                }
+                    Bound::Excluded(e) => {
+                        keys.partition_point(|k| cmp.compare(k.borrow(), e).is_lt())
+                    }
+                    Bound::Unbounded => children.len() - 1,
+                };
+                (first, last)
+            }
+        };
+
+        let mut covered_entries = Vec::new();
+        if last > first + 1 {
+            // Children strictly between the boundaries are fully covered
+            let Node::Internal { keys, children, counts } = &mut self.nodes[node] else {
+                unreachable!("node is internal");
+            };
+            keys.drain(first..last - 1);
+            counts.drain(first + 1..last);
+            let covered: Vec<NodeId> = children.drain(first + 1..last).collect();
+            let (left, right) = (children[first], children[first + 1]);
+            for child in covered {
+                self.release_subtree(child, &mut covered_entries);
+            }
+
+            let left_edge = self.rightmost_leaf_of(left);
+            let right_edge = self.leftmost_leaf_of(right);
+            if let Node::Leaf { next, .. } = &mut self.nodes[left_edge] {
+                *next = Some(right_edge);
+            }
+            if let Node::Leaf { prev, .. } = &mut self.nodes[right_edge] {
+                *prev = Some(left_edge);
             }
         }
-    }
 
-    /// Get all keys in sorted order
-    pub fn all_keys(&self) -> Vec<i32> {
-        let mut keys = Vec::new();
-        self.collect_keys(&self.root, &mut keys);
-        keys
+        let children = self.children(node);
+        let (left, right) = (children[first], children.get(first + 1).copied());
+        self.take_range_from(left, range, out);
+        if last > first {
+            out.append(&mut covered_entries);
+            let right = right.expect("boundary children are distinct");
+            self.take_range_from(right, range, out);
+        }
+
+        self.recount(node);
+        self.fix_underflow(node);
     }
 
-    fn collect_keys(&self, node: &Node, keys: &mut Vec<i32>) {
-        match node {
-            Node::Leaf { entries } => {
-                for entry in entries {
-                    keys.push(entry.key);
-                }
+    /// Rebalance until no child of `node` underflows
+    ///
+    /// Range deletion can leave boundary children arbitrarily small, including
+    /// internal nodes with a single underflowing child that can only be fixed
+    /// once a borrow or merge at this level has given it siblings, so descend
+    /// again wherever that happened.
+    fn fix_underflow(&mut self, node: NodeId) {
+        loop {
+            while self.children(node).len() > 1 {
+                let Some(idx) = self.underflowing_child(node) else {
+                    break;
+                };
+                self.rebalance_child(node, idx);
+            }
+
+            let nested: Vec<NodeId> = self
+                .children(node)
+                .iter()
+                .copied()
+                .filter(|&c| self.children(c).len() > 1 && self.underflowing_child(c).is_some())
+                .collect();
+            if nested.is_empty() {
+                break;
             }
+            for child in nested {
+                self.fix_underflow(child);
+            }
+        }
+    }
+
+    fn underflowing_child(&self, node: NodeId) -> Option<usize> {
+        self.children(node)
+            .iter()
+            .position(|&child| self.nodes[child].is_underflow(self.min_degree))
+    }
+
+    /// Release a node and all its descendants, moving their entries into `out`
+    fn release_subtree(&mut self, id: NodeId, out: &mut Vec<Entry<K, V>>) {
+        match self.release(id) {
+            Node::Leaf { entries, .. } => out.extend(entries),
             Node::Internal { children, .. } => {
                 for child in children {
-                    self.collect_keys(child, keys);
+                    self.release_subtree(child, out);
                 }
             }
         }
     }
 
-    /// Print tree structure
-    pub fn print_tree(&self) {
-        println!("B+ Tree (min_degree = {})", MIN_DEGREE);
-        println!("Height: {}", self.height);
-        self.print_node(&self.root, 0);
+    /// Split the tree at `key`, leaving keys `< key` in place and returning a
+    /// new tree with the keys `>= key`
+    pub fn split_off<Q>(&mut self, key: &Q) -> Self
+    where
+        K: Borrow<Q>,
+        Q: ?Sized,
+        C: Comparator<Q> + Clone,
+    {
+        let entries = self.take_range((Bound::Included(key), Bound::Unbounded));
+        let mut right = Self::with_degree_and_comparator(self.min_degree, self.cmp.clone());
+        right.rebuild(entries);
+        right
     }
 
-    fn print_node(&self, node: &Node, level: usize) {
-        let indent = "  ".repeat(level);
-        match node {
-            Node::Leaf { entries } => {
-                let keys: Vec<i32> = entries.iter().map(|e| e.key).collect();
-                println!("{}Leaf: {:?}", indent, keys);
-                for entry in entries {
-                    println!("{}  {} -> {}", indent, entry.key, entry.value);
-                }
+    /// Combine this tree with `other`
+    ///
+    /// When every key of one tree sorts before every key of the other and both
+    /// share a degree, the shorter tree is grafted onto the edge of the taller
+    /// one and only the nodes along that spine are rebalanced or split.
+    /// Otherwise the trees are merged as two sorted leaf streams, with
+    /// `resolve(key, ours, theirs)` choosing the value for keys present in
+    /// both. The result keeps this tree's degree; both trees must order their
+    /// keys the same way.
+    pub fn merge<F>(mut self, mut other: Self, resolve: F) -> Self
+    where
+        F: FnMut(&K, V, V) -> V,
+    {
+        let (Some((self_first, _)), Some((other_first, _))) =
+            (self.first_key_value(), other.first_key_value())
+        else {
+            return if self.is_empty() { other } else { self };
+        };
+        let self_last = self.last_key_value().expect("tree is non-empty").0;
+        let other_last = other.last_key_value().expect("tree is non-empty").0;
+        let same_degree = self.min_degree == other.min_degree;
+
+        if same_degree && self.cmp.compare(self_last, other_first).is_lt() {
+            self.graft(other);
+            self
+        } else if same_degree && self.cmp.compare(other_last, self_first).is_lt() {
+            other.graft(self);
+            other
+        } else {
+            let (ours, theirs) = (self.take_entries(), other.take_entries());
+            let merged = merge_sorted(ours, theirs, &self.cmp, resolve);
+            self.rebuild(merged);
+            self
+        }
+    }
+
+    /// Append `right`, whose keys all sort after this tree's, by linking its
+    /// nodes in rather than re-inserting its entries
+    fn graft(&mut self, right: Self) {
+        let separator = right.first_key_value().expect("right tree is non-empty").0.clone();
+        let (right_height, right_len) = (right.height, right.len);
+        let left_edge = self.rightmost_leaf();
+        let right_root = self.absorb(right);
+        let right_edge = self.leftmost_leaf_of(right_root);
+        if let Node::Leaf { next, .. } = &mut self.nodes[left_edge] {
+            *next = Some(right_edge);
+        }
+        if let Node::Leaf { prev, .. } = &mut self.nodes[right_edge] {
+            *prev = Some(left_edge);
+        }
+        self.len += right_len;
+
+        let split = match self.height.cmp(&right_height) {
+            Ordering::Equal => Some((separator, right_root)),
+            Ordering::Greater => {
+                let depth = self.height - right_height;
+                self.attach(self.root, depth, right_root, separator, true)
             }
-            Node::Internal { keys, children } => {
-                println!("{}Internal: {:?}", indent, keys);
-                for child in children {
-                    self.print_node(child, level + 1);
+            Ordering::Less => {
+                let left_root = std::mem::replace(&mut self.root, right_root);
+                let left_height = std::mem::replace(&mut self.height, right_height);
+                self.attach(right_root, right_height - left_height, left_root, separator, false)
+            }
+        };
+        if let Some((key, sibling)) = split {
+            let old_root = self.root;
+            self.root = self.alloc_internal(vec![key], vec![old_root, sibling]);
+            self.height += 1;
+            self.fix_underflow(self.root);
+        }
+        self.collapse_root();
+    }
+
+    /// Hang `subtree` off the last (or first) child at the level `depth` below
+    /// `node`, returning a new right sibling if `node` had to be split
+    fn attach(
+        &mut self,
+        node: NodeId,
+        depth: usize,
+        subtree: NodeId,
+        separator: K,
+        at_end: bool,
+    ) -> Option<(K, NodeId)> {
+        let Node::Internal { keys, children, .. } = &mut self.nodes[node] else {
+            unreachable!("attach point is above the subtree's level");
+        };
+        if depth == 1 {
+            if at_end {
+                keys.push(separator);
+                children.push(subtree);
+            } else {
+                keys.insert(0, separator);
+                children.insert(0, subtree);
+            }
+        } else {
+            let child = if at_end { children[children.len() - 1] } else { children[0] };
+            if let Some((key, sibling)) = self.attach(child, depth - 1, subtree, separator, at_end) {
+                let Node::Internal { keys, children, .. } = &mut self.nodes[node] else {
+                    unreachable!("node is internal");
+                };
+                let idx = if at_end { keys.len() } else { 0 };
+                keys.insert(idx, key);
+                children.insert(idx + 1, sibling);
+            }
+        }
+
+        self.recount(node);
+        self.fix_underflow(node);
+        self.split_internal(node)
+    }
+
+    /// Split an internal node holding more than the maximum number of keys,
+    /// returning the promoted key and the new right half
+    fn split_internal(&mut self, node: NodeId) -> Option<(K, NodeId)> {
+        let Node::Internal { keys, .. } = &self.nodes[node] else {
+            return None;
+        };
+        if keys.len() < 2 * self.min_degree {
+            return None;
+        }
+        Some(self.halve_internal(node))
+    }
+
+    /// Move the upper half of an internal node into a new right sibling,
+    /// returning the promoted middle key with the sibling's id
+    fn halve_internal(&mut self, node: NodeId) -> (K, NodeId) {
+        let _scope = profile::scope(Phase::Split);
+        let Node::Internal { keys, children, counts } = &mut self.nodes[node] else {
+            unreachable!("only internal nodes are halved");
+        };
+        let mid = keys.len() / 2;
+        let right_keys = keys.split_off(mid + 1);
+        let promoted = keys.pop().expect("mid key is present");
+        let right_children = children.split_off(mid + 1);
+        let right_counts = counts.split_off(mid + 1);
+        let right = self.alloc(Node::Internal {
+            keys: right_keys,
+            children: right_children,
+            counts: right_counts,
+        });
+        (promoted, right)
+    }
+
+    /// Move another tree's nodes into this arena, returning its root's new id
+    fn absorb(&mut self, other: Self) -> NodeId {
+        let offset = self.nodes.len();
+        for mut node in other.nodes {
+            match &mut node {
+                Node::Leaf { prev, next, .. } => {
+                    for id in [prev, next].into_iter().flatten() {
+                        *id += offset;
+                    }
+                }
+                Node::Internal { children, .. } => {
+                    children.iter_mut().for_each(|id| *id += offset);
                 }
             }
+            self.nodes.push(node);
         }
+        self.free.extend(other.free.iter().map(|id| id + offset));
+        other.root + offset
     }
-}
 
-impl fmt::Display for BPlusTree {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(
-            f,
-            "B+ Tree(height={}, keys={:?})",
-            self.height,
-            self.all_keys()
-        )
+    /// Move all entries out of the leaves in ascending key order
+    fn take_entries(&mut self) -> Vec<Entry<K, V>> {
+        let mut all = Vec::with_capacity(self.len);
+        let mut leaf = Some(self.leftmost_leaf());
+        while let Some(Node::Leaf { entries, next, .. }) = leaf.map(|id| &mut self.nodes[id]) {
+            all.append(entries);
+            leaf = *next;
+        }
+        all
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+    /// Replace the tree's contents with `entries`, which must be sorted by key
+    ///
+    /// Packs leaves and then each internal level bottom-up, spreading items
+    /// evenly so every non-root node meets the minimum occupancy.
+    fn rebuild(&mut self, entries: Vec<Entry<K, V>>) {
+        let len = entries.len();
+        self.clear();
+        if len == 0 {
+            return;
+        }
+        self.nodes.clear();
 
-    #[test]
-    fn test_insert_and_search() {
-        let mut tree = BPlusTree::new();
-        tree.insert(10, "ten".to_string());
-        tree.insert(20, "twenty".to_string());
-        tree.insert(5, "five".to_string());
+        let mut level: Vec<(K, NodeId)> = Vec::new();
+        for chunk in even_chunks(entries, 2 * self.min_degree - 2) {
+            let id = self.nodes.len();
+            let prev = level.last().map(|&(_, prev)| prev);
+            level.push((chunk[0].key.clone(), id));
+            self.nodes.push(Node::Leaf {
+                entries: chunk,
+                prev,
+                next: None,
+            });
+            if let Some(Node::Leaf { next, .. }) = prev.map(|prev| &mut self.nodes[prev]) {
+                *next = Some(id);
+            }
+        }
+
+        while level.len() > 1 {
+            let mut parents = Vec::new();
+            for group in even_chunks(level, 2 * self.min_degree - 1) {
+                let mut group = group.into_iter();
+                let (min_key, first) = group.next().expect("chunks are non-empty");
+                let (keys, rest): (Vec<K>, Vec<NodeId>) = group.unzip();
+                let mut children = vec![first];
+                children.extend(rest);
+                let counts = children.iter().map(|&c| self.subtree_len(c)).collect();
+                parents.push((min_key, self.nodes.len()));
+                self.nodes.push(Node::Internal {
+                    keys,
+                    children,
+                    counts,
+                });
+            }
+            level = parents;
+            self.height += 1;
+        }
 
-        assert_eq!(tree.search(10), Some("ten".to_string()));
-        assert_eq!(tree.search(20), Some("twenty".to_string()));
-        assert_eq!(tree.search(5), Some("five".to_string()));
-        assert_eq!(tree.search(100), None);
+        self.root = level[0].1;
+        self.len = len;
     }
 
-    #[test]
-    fn test_range_query() {
-        let mut tree = BPlusTree::new();
-        for i in 1..=10 {
-            tree.insert(i * 10, format!("value_{}", i));
+    fn remove_from<Q>(&mut self, node: NodeId, key: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized,
+        C: Comparator<Q>,
+    {
+        match &mut self.nodes[node] {
+            Node::Leaf { entries, .. } => {
+                let _scope = profile::scope(Phase::LeafSearch);
+                let pos = entries
+                    .binary_search_by(|e| self.cmp.compare(e.key.borrow(), key))
+                    .ok()?;
+                Some(entries.remove(pos).value)
+            }
+            Node::Internal { keys, children, .. } => {
+                let child_idx = {
+                    let _scope = profile::scope(Phase::Descend);
+                    keys.partition_point(|k| self.cmp.compare(k.borrow(), key).is_le())
+                };
+                let child = children[child_idx];
+                let removed = self.remove_from(child, key)?;
+                if let Node::Internal { counts, .. } = &mut self.nodes[node] {
+                    counts[child_idx] -= 1;
+                }
+                if self.nodes[child].is_underflow(self.min_degree) {
+                    self.rebalance_child(node, child_idx);
+                }
+                Some(removed)
+            }
         }
+    }
 
-        let result = tree.range_query(25, 75);
-        assert!(!result.is_empty());
+    /// Restore the minimum occupancy of a parent's `child_idx`th child by
+    /// borrowing from a sibling, or merging with one when neither can spare a key
+    fn rebalance_child(&mut self, parent: NodeId, child_idx: usize) {
+        let children = self.children(parent);
+        let child = children[child_idx];
+        let left = child_idx.checked_sub(1).map(|i| children[i]);
+        let right = children.get(child_idx + 1).copied();
+
+        if let Some(lender) = left.filter(|&id| self.nodes[id].can_lend(self.min_degree)) {
+            let [parent_node, lender_node, child_node] = self
+                .nodes
+                .get_disjoint_mut([parent, lender, child])
+                .expect("parent and siblings are distinct nodes");
+            let Node::Internal { keys, .. } = parent_node else {
+                unreachable!("parent is an internal node");
+            };
+            match (lender_node, child_node) {
+                (Node::Leaf { entries: lender_entries, .. }, Node::Leaf { entries, .. }) => {
+                    let entry = lender_entries.pop().expect("lender has spare entries");
+                    keys[child_idx - 1] = entry.key.clone();
+                    entries.insert(0, entry);
+                }
+                (
+                    Node::Internal {
+                        keys: lender_keys,
+                        children: lender_children,
+                        counts: lender_counts,
+                    },
+                    Node::Internal {
+                        keys: child_keys,
+                        children: child_children,
+                        counts: child_counts,
+                    },
+                ) => {
+                    let moved_key = lender_keys.pop().expect("lender has spare keys");
+                    let moved_child = lender_children.pop().expect("lender has children");
+                    child_keys.insert(0, std::mem::replace(&mut keys[child_idx - 1], moved_key));
+                    child_children.insert(0, moved_child);
+                    child_counts.insert(0, lender_counts.pop().expect("lender has counts"));
+                }
+                _ => unreachable!("siblings are at the same level"),
+            }
+        } else if let Some(lender) = right.filter(|&id| self.nodes[id].can_lend(self.min_degree)) {
+            let [parent_node, child_node, lender_node] = self
+                .nodes
+                .get_disjoint_mut([parent, child, lender])
+                .expect("parent and siblings are distinct nodes");
+            let Node::Internal { keys, .. } = parent_node else {
+                unreachable!("parent is an internal node");
+            };
+            match (child_node, lender_node) {
+                (Node::Leaf { entries, .. }, Node::Leaf { entries: lender_entries, .. }) => {
+                    entries.push(lender_entries.remove(0));
+                    keys[child_idx] = lender_entries[0].key.clone();
+                }
+                (
+                    Node::Internal {
+                        keys: child_keys,
+                        children: child_children,
+                        counts: child_counts,
+                    },
+                    Node::Internal {
+                        keys: lender_keys,
+                        children: lender_children,
+                        counts: lender_counts,
+                    },
+                ) => {
+                    let moved_key = lender_keys.remove(0);
+                    child_keys.push(std::mem::replace(&mut keys[child_idx], moved_key));
+                    child_children.push(lender_children.remove(0));
+                    child_counts.push(lender_counts.remove(0));
+                }
+                _ => unreachable!("siblings are at the same level"),
+            }
+        } else if left.is_some() {
+            self.merge_children(parent, child_idx - 1);
+        } else if right.is_some() {
+            self.merge_children(parent, child_idx);
+        }
+        self.recount(parent);
+    }
+
+    /// Merge a parent's `idx + 1`th child into its `idx`th, dropping their separator
+    fn merge_children(&mut self, parent: NodeId, idx: usize) {
+        let Node::Internal { keys, children, .. } = &mut self.nodes[parent] else {
+            unreachable!("parent is an internal node");
+        };
+        let separator = keys.remove(idx);
+        let left = children[idx];
+        let right = children.remove(idx + 1);
+
+        let right_node = self.release(right);
+        match (&mut self.nodes[left], right_node) {
+            (
+                Node::Leaf { entries, next, .. },
+                Node::Leaf { entries: right_entries, next: right_next, .. },
+            ) => {
+                entries.extend(right_entries);
+                *next = right_next;
+                if let Some(Node::Leaf { prev, .. }) = right_next.map(|id| &mut self.nodes[id]) {
+                    *prev = Some(left);
+                }
+            }
+            (
+                Node::Internal {
+                    keys: left_keys,
+                    children: left_children,
+                    counts: left_counts,
+                },
+                Node::Internal {
+                    keys: right_keys,
+                    children: right_children,
+                    counts: right_counts,
+                },
+            ) => {
+                left_keys.push(separator);
+                left_keys.extend(right_keys);
+                left_children.extend(right_children);
+                left_counts.extend(right_counts);
+            }
+            _ => unreachable!("siblings are at the same level"),
+        }
+    }
+
+    /// Search for a value by key
+    pub fn search<Q>(&self, key: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized,
+        C: Comparator<Q>,
+        V: Clone,
+    {
+        let leaf = self.find_leaf(key);
+        let _scope = profile::scope(Phase::LeafSearch);
+        match &self.nodes[leaf] {
+            Node::Leaf { entries, .. } => entries
+                .binary_search_by(|e| self.cmp.compare(e.key.borrow(), key))
+                .ok()
+                .map(|pos| entries[pos].value.clone()),
+            Node::Internal { .. } => None,
+        }
+    }
+
+    /// Whether `key` is present, without cloning its value
+    pub fn contains_key<Q>(&self, key: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: ?Sized,
+        C: Comparator<Q>,
+    {
+        let leaf = self.find_leaf(key);
+        let _scope = profile::scope(Phase::LeafSearch);
+        match &self.nodes[leaf] {
+            Node::Leaf { entries, .. } => entries
+                .binary_search_by(|e| self.cmp.compare(e.key.borrow(), key))
+                .is_ok(),
+            Node::Internal { .. } => false,
+        }
+    }
+
+    /// Get a mutable reference to the value stored under `key`
+    pub fn get_mut<Q>(&mut self, key: &Q) -> Option<&mut V>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized,
+        C: Comparator<Q>,
+    {
+        let leaf = self.find_leaf(key);
+        let _scope = profile::scope(Phase::LeafSearch);
+        match &mut self.nodes[leaf] {
+            Node::Leaf { entries, .. } => entries
+                .binary_search_by(|e| self.cmp.compare(e.key.borrow(), key))
+                .ok()
+                .map(|pos| &mut entries[pos].value),
+            Node::Internal { .. } => None,
+        }
+    }
+
+    /// Get the entry for `key` for in-place insertion or modification
+    pub fn entry(&mut self, key: K) -> TreeEntry<'_, K, V, C> {
+        let leaf = self.find_leaf(&key);
+        let found = {
+            let _scope = profile::scope(Phase::LeafSearch);
+            match &self.nodes[leaf] {
+                Node::Leaf { entries, .. } => entries
+                    .binary_search_by(|e| self.cmp.compare(&e.key, &key))
+                    .ok(),
+                Node::Internal { .. } => None,
+            }
+        };
+        match found {
+            Some(idx) => TreeEntry::Occupied(OccupiedEntry {
+                tree: self,
+                leaf,
+                idx,
+            }),
+            None => TreeEntry::Vacant(VacantEntry { tree: self, key }),
+        }
+    }
+
+    /// Smallest entry, found by descending the leftmost path
+    pub fn first_key_value(&self) -> Option<(&K, &V)> {
+        match &self.nodes[self.leftmost_leaf()] {
+            Node::Leaf { entries, .. } => entries.first().map(|e| (&e.key, &e.value)),
+            Node::Internal { .. } => None,
+        }
+    }
+
+    /// Largest entry, found by descending the rightmost path
+    pub fn last_key_value(&self) -> Option<(&K, &V)> {
+        match &self.nodes[self.rightmost_leaf()] {
+            Node::Leaf { entries, .. } => entries.last().map(|e| (&e.key, &e.value)),
+            Node::Internal { .. } => None,
+        }
+    }
+
+    /// Entry with the largest key at or below `key`
+    pub fn floor<Q>(&self, key: &Q) -> Option<(&K, &V)>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized,
+        C: Comparator<Q>,
+    {
+        self.range((Bound::Unbounded, Bound::Included(key))).next_back()
+    }
+
+    /// Entry with the smallest key at or above `key`
+    pub fn ceiling<Q>(&self, key: &Q) -> Option<(&K, &V)>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized,
+        C: Comparator<Q>,
+    {
+        self.range((Bound::Included(key), Bound::Unbounded)).next()
+    }
+
+    /// Entry whose key is closest to `key` under `distance`, with `tie`
+    /// deciding between equally distant neighbours on either side
+    ///
+    /// Only the floor and ceiling of `key` are compared, so `distance` should
+    /// grow with how far apart two keys sort, e.g. `|a, b| a.abs_diff(*b)` for
+    /// integers or the gap between two timestamps.
+    pub fn nearest<D, F>(&self, key: &K, tie: Tie, distance: F) -> Option<(&K, &V)>
+    where
+        D: Ord,
+        F: Fn(&K, &K) -> D,
+    {
+        let below = self.floor(key);
+        if below.is_some_and(|(k, _)| self.cmp.compare(k, key).is_eq()) {
+            return below;
+        }
+        let above = self.range((Bound::Excluded(key), Bound::Unbounded)).next();
+        match (below, above) {
+            (Some(lo), Some(hi)) => match distance(key, lo.0).cmp(&distance(key, hi.0)) {
+                Ordering::Less => Some(lo),
+                Ordering::Greater => Some(hi),
+                Ordering::Equal => match tie {
+                    Tie::Lower => Some(lo),
+                    Tie::Higher => Some(hi),
+                },
+            },
+            (lo, hi) => lo.or(hi),
+        }
+    }
+
+    /// The `k`th smallest entry, counting from zero
+    ///
+    /// Descends using the per-child entry counts, so this costs O(log n)
+    /// rather than a scan; handy for percentiles and paging by offset.
+    pub fn select(&self, mut k: usize) -> Option<(&K, &V)> {
+        if k >= self.len {
+            return None;
+        }
+        let mut node = self.root;
+        while let Node::Internal { children, counts, .. } = &self.nodes[node] {
+            let mut idx = 0;
+            while k >= counts[idx] {
+                k -= counts[idx];
+                idx += 1;
+            }
+            node = children[idx];
+        }
+        match &self.nodes[node] {
+            Node::Leaf { entries, .. } => entries.get(k).map(|e| (&e.key, &e.value)),
+            Node::Internal { .. } => None,
+        }
+    }
+
+    /// Draw `n` distinct entries uniformly at random, returned in ascending key
+    /// order; asking for more entries than the tree holds returns them all
+    ///
+    /// Picks distinct ranks with Floyd's algorithm and resolves each through
+    /// `select`, so it costs O(n log len) without walking the leaves.
+    pub fn sample<R: RngCore + ?Sized>(&self, n: usize, rng: &mut R) -> Vec<(&K, &V)> {
+        let n = n.min(self.len);
+        let mut ranks = BTreeSet::new();
+        for upper in self.len - n..self.len {
+            let rank = uniform_below(rng, upper + 1);
+            if !ranks.insert(rank) {
+                ranks.insert(upper);
+            }
+        }
+        ranks.into_iter().filter_map(|rank| self.select(rank)).collect()
+    }
+
+    /// Number of keys strictly less than `key`, whether or not `key` is present
+    pub fn rank<Q>(&self, key: &Q) -> usize
+    where
+        K: Borrow<Q>,
+        Q: ?Sized,
+        C: Comparator<Q>,
+    {
+        let mut rank = 0;
+        let mut node = self.root;
+        while let Node::Internal { keys, children, counts } = &self.nodes[node] {
+            let idx = keys.partition_point(|k| self.cmp.compare(k.borrow(), key).is_le());
+            rank += counts[..idx].iter().sum::<usize>();
+            node = children[idx];
+        }
+        match &self.nodes[node] {
+            Node::Leaf { entries, .. } => {
+                rank + entries.partition_point(|e| self.cmp.compare(e.key.borrow(), key).is_lt())
+            }
+            Node::Internal { .. } => rank,
+        }
+    }
+
+    /// Descend to the leaf where `key` is or would be stored
+    fn find_leaf<Q>(&self, key: &Q) -> NodeId
+    where
+        K: Borrow<Q>,
+        Q: ?Sized,
+        C: Comparator<Q>,
+    {
+        let mut node = self.root;
+        while let Node::Internal { keys, children, .. } = &self.nodes[node] {
+            let _scope = profile::scope(Phase::Descend);
+            node = children[keys.partition_point(|k| self.cmp.compare(k.borrow(), key).is_le())];
+        }
+        node
+    }
+
+    fn leftmost_leaf(&self) -> NodeId {
+        self.leftmost_leaf_of(self.root)
+    }
+
+    fn rightmost_leaf(&self) -> NodeId {
+        self.rightmost_leaf_of(self.root)
+    }
+
+    fn leftmost_leaf_of(&self, mut node: NodeId) -> NodeId {
+        while let Node::Internal { children, .. } = &self.nodes[node] {
+            node = children[0];
+        }
+        node
+    }
+
+    fn rightmost_leaf_of(&self, mut node: NodeId) -> NodeId {
+        while let Node::Internal { children, .. } = &self.nodes[node] {
+            node = children[children.len() - 1];
+        }
+        node
+    }
+
+    /// Range query: find all entries in range [start, end]
+    pub fn range_query(&self, start: &K, end: &K) -> Vec<(K, V)>
+    where
+        V: Clone,
+    {
+        self.range((Bound::Included(start), Bound::Included(end)))
+            .map(|(k, v)| (k.clone(), v.clone()))
+            .collect()
+    }
+
+    /// Iterate lazily over the entries whose keys fall within `range`
+    ///
+    /// Descends once per bound to place the front and back cursors, then walks
+    /// the leaf chain. Like `iter()`, the result is double-ended.
+    pub fn range<T, R>(&self, range: R) -> Iter<'_, K, V>
+    where
+        K: Borrow<T>,
+        T: ?Sized,
+        C: Comparator<T>,
+        R: RangeBounds<T>,
+    {
+        if is_empty_range(&self.cmp, &range) {
+            let leaf = self.leftmost_leaf();
+            return Iter {
+                nodes: &self.nodes,
+                front: (leaf, 0),
+                back: (leaf, 0),
+            };
+        }
+
+        let front = match range.start_bound() {
+            Bound::Included(s) => self.seek(s, |k| self.cmp.compare(k, s).is_lt()),
+            Bound::Excluded(s) => self.seek(s, |k| self.cmp.compare(k, s).is_le()),
+            Bound::Unbounded => (self.leftmost_leaf(), 0),
+        };
+        let back = match range.end_bound() {
+            Bound::Included(e) => self.seek(e, |k| self.cmp.compare(k, e).is_le()),
+            Bound::Excluded(e) => self.seek(e, |k| self.cmp.compare(k, e).is_lt()),
+            Bound::Unbounded => {
+                let last = self.rightmost_leaf();
+                (last, self.nodes[last].num_keys())
+            }
+        };
+        Iter {
+            nodes: &self.nodes,
+            front,
+            back,
+        }
+    }
+
+    /// Cursor in the leaf for `key`, just past the entries matching `before`
+    fn seek<T>(&self, key: &T, before: impl Fn(&T) -> bool) -> (NodeId, usize)
+    where
+        K: Borrow<T>,
+        T: ?Sized,
+        C: Comparator<T>,
+    {
+        let leaf = self.find_leaf(key);
+        let _scope = profile::scope(Phase::LeafSearch);
+        let idx = match &self.nodes[leaf] {
+            Node::Leaf { entries, .. } => entries.partition_point(|e| before(e.key.borrow())),
+            Node::Internal { .. } => 0,
+        };
+        (leaf, idx)
+    }
+
+    /// Get all keys in sorted order
+    pub fn all_keys(&self) -> Vec<K> {
+        self.keys().cloned().collect()
+    }
+
+    /// Iterate over entries in ascending key order
+    ///
+    /// The iterator is double-ended, so `iter().rev()` walks from the largest key
+    /// down through the leaves' `prev` links.
+    pub fn iter(&self) -> Iter<'_, K, V> {
+        let last = self.rightmost_leaf();
+        Iter {
+            nodes: &self.nodes,
+            front: (self.leftmost_leaf(), 0),
+            back: (last, self.nodes[last].num_keys()),
+        }
+    }
+
+    /// Iterate over keys in ascending order
+    pub fn keys(&self) -> Keys<'_, K, V> {
+        Keys(self.iter())
+    }
+
+    /// Iterate over values in ascending key order
+    pub fn values(&self) -> Values<'_, K, V> {
+        Values(self.iter())
+    }
+
+    /// Shape and size of the tree, for seeing how an insert pattern affects
+    /// its structure
+    pub fn stats(&self) -> TreeStats {
+        let mut nodes_per_level = Vec::with_capacity(self.height);
+        let mut leaf_entries = 0;
+        let mut total_keys = 0;
+        let mut heap_bytes = 0;
+        let mut level = vec![self.root];
+        while !level.is_empty() {
+            nodes_per_level.push(level.len());
+            let mut below = Vec::new();
+            for &id in &level {
+                match &self.nodes[id] {
+                    Node::Leaf { entries, .. } => {
+                        leaf_entries += entries.len();
+                        total_keys += entries.len();
+                        heap_bytes += entries.capacity() * std::mem::size_of::<Entry<K, V>>();
+                    }
+                    Node::Internal { keys, children, counts } => {
+                        total_keys += keys.len();
+                        heap_bytes += keys.capacity() * std::mem::size_of::<K>()
+                            + children.capacity() * std::mem::size_of::<NodeId>()
+                            + counts.capacity() * std::mem::size_of::<usize>();
+                        below.extend_from_slice(children);
+                    }
+                }
+            }
+            level = below;
+        }
+
+        let node_count: usize = nodes_per_level.iter().sum();
+        let leaves = nodes_per_level.last().copied().unwrap_or(0);
+        let leaf_capacity = leaves * (2 * self.min_degree - 1);
+        TreeStats {
+            height: self.height,
+            len: self.len,
+            node_count,
+            leaf_fill_factor: ratio(leaf_entries, leaf_capacity),
+            avg_entries_per_node: ratio(total_keys, node_count),
+            memory_bytes: std::mem::size_of::<Self>()
+                + self.nodes.capacity() * std::mem::size_of::<Node<K, V>>()
+                + self.free.capacity() * std::mem::size_of::<NodeId>()
+                + heap_bytes,
+            nodes_per_level,
+        }
+    }
+
+    /// Check the structural invariants of the tree, reporting the first
+    /// violation found and the node it was found in
+    ///
+    /// Covers key ordering, occupancy bounds, uniform leaf depth, separator
+    /// bounds, the leaf chain and the cached length. Intended for tests and
+    /// debugging; it visits every node.
+    pub fn validate(&self) -> Result<(), InvariantViolation> {
+        let mut leaves = Vec::new();
+        let entries = self.validate_node(self.root, 1, None, None, &mut leaves)?;
+
+        for (i, &leaf) in leaves.iter().enumerate() {
+            let Node::Leaf { prev, next, .. } = &self.nodes[leaf] else {
+                unreachable!("only leaves are collected");
+            };
+            let expected_prev = i.checked_sub(1).map(|j| leaves[j]);
+            if *prev != expected_prev || *next != leaves.get(i + 1).copied() {
+                return Err(InvariantViolation::BrokenLeafChain { leaf });
+            }
+        }
+
+        if entries != self.len {
+            return Err(InvariantViolation::LenMismatch {
+                len: self.len,
+                entries,
+            });
+        }
+        Ok(())
+    }
+
+    /// Validate the subtree at `id`, whose keys must lie in `[lower, upper)`,
+    /// returning its entry count and appending its leaves in key order
+    fn validate_node(
+        &self,
+        id: NodeId,
+        depth: usize,
+        lower: Option<&K>,
+        upper: Option<&K>,
+        leaves: &mut Vec<NodeId>,
+    ) -> Result<usize, InvariantViolation> {
+        let node = &self.nodes[id];
+        let keys = node.num_keys();
+        let max = 2 * self.min_degree - 1;
+        if id != self.root && node.is_underflow(self.min_degree) {
+            let min = self.min_degree - 1;
+            return Err(InvariantViolation::Underflow { node: id, keys, min });
+        }
+        if keys > max {
+            return Err(InvariantViolation::Overflow { node: id, keys, max });
+        }
+        let cmp = &self.cmp;
+        let in_bounds = |k: &K| {
+            lower.is_none_or(|lo| cmp.compare(lo, k).is_le())
+                && upper.is_none_or(|hi| cmp.compare(k, hi).is_lt())
+        };
+
+        match node {
+            Node::Leaf { entries, .. } => {
+                if depth != self.height {
+                    let height = self.height;
+                    return Err(InvariantViolation::WrongDepth { node: id, depth, height });
+                }
+                if !entries.windows(2).all(|w| cmp.compare(&w[0].key, &w[1].key).is_lt()) {
+                    return Err(InvariantViolation::KeysOutOfOrder { node: id });
+                }
+                if !entries.iter().all(|e| in_bounds(&e.key)) {
+                    return Err(InvariantViolation::OutsideSeparators { node: id });
+                }
+                leaves.push(id);
+                Ok(entries.len())
+            }
+            Node::Internal { keys, children, counts } => {
+                // Also stops the walk from looping forever on a cyclic arena
+                if depth >= self.height {
+                    let height = self.height;
+                    return Err(InvariantViolation::WrongDepth { node: id, depth, height });
+                }
+                if children.len() != keys.len() + 1 {
+                    return Err(InvariantViolation::ChildCountMismatch {
+                        node: id,
+                        keys: keys.len(),
+                        children: children.len(),
+                    });
+                }
+                if keys.is_empty() {
+                    return Err(InvariantViolation::Underflow { node: id, keys: 0, min: 1 });
+                }
+                if !keys.windows(2).all(|w| cmp.compare(&w[0], &w[1]).is_lt()) {
+                    return Err(InvariantViolation::KeysOutOfOrder { node: id });
+                }
+                if !keys.iter().all(in_bounds) {
+                    return Err(InvariantViolation::OutsideSeparators { node: id });
+                }
+
+                let mut entries = 0;
+                for (i, &child) in children.iter().enumerate() {
+                    let lo = if i == 0 { lower } else { Some(&keys[i - 1]) };
+                    let hi = keys.get(i).or(upper);
+                    let below = self.validate_node(child, depth + 1, lo, hi, leaves)?;
+                    if counts.get(i) != Some(&below) {
+                        return Err(InvariantViolation::StaleCount {
+                            node: id,
+                            child: i,
+                            recorded: counts.get(i).copied(),
+                            entries: below,
+                        });
+                    }
+                    entries += below;
+                }
+                if let Some(&extra) = counts.get(children.len()) {
+                    return Err(InvariantViolation::StaleCount {
+                        node: id,
+                        child: children.len(),
+                        recorded: Some(extra),
+                        entries: 0,
+                    });
+                }
+                Ok(entries)
+            }
+        }
+    }
+}
+
+/// Uniform integer in `0..bound`, rejecting draws from the biased tail
+fn uniform_below<R: RngCore + ?Sized>(rng: &mut R, bound: usize) -> usize {
+    let bound = bound as u64;
+    let zone = u64::MAX - u64::MAX % bound;
+    loop {
+        let draw = rng.next_u64();
+        if draw < zone {
+            return (draw % bound) as usize;
+        }
+    }
+}
+
+/// `part / whole`, or zero for an empty whole
+fn ratio(part: usize, whole: usize) -> f64 {
+    if whole == 0 {
+        0.0
+    } else {
+        part as f64 / whole as f64
+    }
+}
+
+/// Whether `range` is empty by construction, i.e. its start lies after its end
+pub(crate) fn is_empty_range<T, C, R>(cmp: &C, range: &R) -> bool
+where
+    T: ?Sized,
+    C: Comparator<T>,
+    R: RangeBounds<T>,
+{
+    match (range.start_bound(), range.end_bound()) {
+        (Bound::Included(s), Bound::Included(e)) => cmp.compare(s, e).is_gt(),
+        (Bound::Included(s) | Bound::Excluded(s), Bound::Included(e) | Bound::Excluded(e)) => {
+            cmp.compare(s, e).is_ge()
+        }
+        _ => false,
+    }
+}
+
+/// Merge two lists of entries sorted by key, combining the values of keys
+/// present in both with `resolve`
+fn merge_sorted<K, V>(
+    left: Vec<Entry<K, V>>,
+    right: Vec<Entry<K, V>>,
+    cmp: &impl Comparator<K>,
+    mut resolve: impl FnMut(&K, V, V) -> V,
+) -> Vec<Entry<K, V>> {
+    let mut merged = Vec::with_capacity(left.len() + right.len());
+    let mut left = left.into_iter().peekable();
+    let mut right = right.into_iter().peekable();
+    loop {
+        let next = match (left.peek(), right.peek()) {
+            (Some(l), Some(r)) => match cmp.compare(&l.key, &r.key) {
+                Ordering::Less => left.next(),
+                Ordering::Greater => right.next(),
+                Ordering::Equal => {
+                    let l = left.next().expect("peeked");
+                    let r = right.next().expect("peeked");
+                    let value = resolve(&l.key, l.value, r.value);
+                    Some(Entry { key: l.key, value })
+                }
+            },
+            (Some(_), None) => left.next(),
+            (None, _) => right.next(),
+        };
+        match next {
+            Some(entry) => merged.push(entry),
+            None => return merged,
+        }
+    }
+}
+
+/// Split `items` into the fewest chunks of at most `max` items whose sizes
+/// differ by at most one
+fn even_chunks<T>(items: Vec<T>, max: usize) -> Vec<Vec<T>> {
+    let count = items.len().div_ceil(max);
+    let (base, extra) = (items.len() / count, items.len() % count);
+    let mut items = items.into_iter();
+    (0..count)
+        .map(|i| items.by_ref().take(base + usize::from(i < extra)).collect())
+        .collect()
+}
+
+impl<V> BPlusTree<String, V> {
+    /// Iterate over the entries whose keys start with `prefix`
+    ///
+    /// Scans `[prefix, successor)` where the successor is the smallest string
+    /// greater than every string with that prefix.
+    pub fn prefix_range(&self, prefix: &str) -> Iter<'_, String, V> {
+        let successor = prefix_successor(prefix);
+        let end = successor.as_deref().map_or(Bound::Unbounded, Bound::Excluded);
+        self.range::<str, _>((Bound::Included(prefix), end))
+    }
+}
+
+/// Smallest string ordered after every string starting with `prefix`, or
+/// `None` if no such string exists (e.g. the prefix is empty)
+fn prefix_successor(prefix: &str) -> Option<String> {
+    let mut chars: Vec<char> = prefix.chars().collect();
+    while let Some(last) = chars.pop() {
+        let next = match last {
+            '\u{D7FF}' => Some('\u{E000}'),
+            c => char::from_u32(c as u32 + 1),
+        };
+        if let Some(next) = next {
+            chars.push(next);
+            return Some(chars.into_iter().collect());
+        }
+    }
+    None
+}
+
+impl<V> BPlusTree<Vec<u8>, V> {
+    /// Iterate over the entries whose keys start with `prefix`
+    ///
+    /// Keys compare as raw bytes, so this also scans serialized composite keys
+    /// by their leading components.
+    pub fn prefix_range(&self, prefix: &[u8]) -> Iter<'_, Vec<u8>, V> {
+        let successor = bytes_successor(prefix);
+        let end = successor.as_deref().map_or(Bound::Unbounded, Bound::Excluded);
+        self.range::<[u8], _>((Bound::Included(prefix), end))
+    }
+}
+
+/// Smallest byte string ordered after every byte string starting with
+/// `prefix`, or `None` if the prefix is empty or all `0xff`
+fn bytes_successor(prefix: &[u8]) -> Option<Vec<u8>> {
+    let end = prefix.iter().rposition(|&b| b != u8::MAX)?;
+    let mut successor = prefix[..=end].to_vec();
+    successor[end] += 1;
+    Some(successor)
+}
+
+impl<K: fmt::Debug + fmt::Display, V: fmt::Display, C> BPlusTree<K, V, C> {
+    /// Print tree structure
+    pub fn print_tree(&self) {
+        self.write_tree(&mut std::io::stdout().lock())
+            .expect("Failed to write to stdout");
+    }
+
+    /// Write the structure shown by `print_tree` to `out`
+    pub fn write_tree<W: std::io::Write + ?Sized>(&self, out: &mut W) -> std::io::Result<()> {
+        writeln!(out, "B+ Tree (min_degree = {})", self.min_degree)?;
+        writeln!(out, "Height: {}", self.height)?;
+        self.write_node(out, self.root, 0)
+    }
+
+    fn write_node<W: std::io::Write + ?Sized>(
+        &self,
+        out: &mut W,
+        node: NodeId,
+        level: usize,
+    ) -> std::io::Result<()> {
+        let indent = "  ".repeat(level);
+        match &self.nodes[node] {
+            Node::Leaf { entries, .. } => {
+                let keys: Vec<&K> = entries.iter().map(|e| &e.key).collect();
+                writeln!(out, "{}Leaf: {:?}", indent, keys)?;
+                for entry in entries {
+                    writeln!(out, "{}  {} -> {}", indent, entry.key, entry.value)?;
+                }
+            }
+            Node::Internal { keys, children, .. } => {
+                writeln!(out, "{}Internal: {:?}", indent, keys)?;
+                for child in children {
+                    self.write_node(out, *child, level + 1)?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Structural summary of a tree, returned by `BPlusTree::stats`
+#[derive(Clone, Debug, PartialEq)]
+pub struct TreeStats {
+    /// Number of levels, counting the leaves
+    pub height: usize,
+    /// Number of entries
+    pub len: usize,
+    /// Number of live nodes, internal and leaf
+    pub node_count: usize,
+    /// Live nodes at each level, from the root down to the leaves
+    pub nodes_per_level: Vec<usize>,
+    /// Entries held by the leaves as a fraction of their combined capacity
+    pub leaf_fill_factor: f64,
+    /// Mean number of keys per node, leaves and internal nodes alike
+    pub avg_entries_per_node: f64,
+    /// Approximate bytes held by the tree's arena and node buffers, excluding
+    /// anything keys or values own on the heap themselves
+    pub memory_bytes: usize,
+}
+
+/// A broken structural invariant, reported by `BPlusTree::validate`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum InvariantViolation {
+    /// Keys within a node are not strictly ascending
+    KeysOutOfOrder { node: NodeId },
+    /// A non-root node holds fewer keys than the minimum
+    Underflow { node: NodeId, keys: usize, min: usize },
+    /// A node holds more keys than the maximum
+    Overflow { node: NodeId, keys: usize, max: usize },
+    /// An internal node does not have exactly one more child than keys
+    ChildCountMismatch { node: NodeId, keys: usize, children: usize },
+    /// A leaf sits at a depth other than the tree's height, or an internal node
+    /// sits at or below it
+    WrongDepth { node: NodeId, depth: usize, height: usize },
+    /// A node holds a key outside the range its parent's separators allow
+    OutsideSeparators { node: NodeId },
+    /// The entry count an internal node keeps for its `child`th child (if any)
+    /// differs from the entries actually beneath it
+    StaleCount {
+        node: NodeId,
+        child: usize,
+        recorded: Option<usize>,
+        entries: usize,
+    },
+    /// A leaf's `prev`/`next` links disagree with the tree's leaf order
+    BrokenLeafChain { leaf: NodeId },
+    /// The cached length differs from the number of entries in the leaves
+    LenMismatch { len: usize, entries: usize },
+}
+
+impl fmt::Display for InvariantViolation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            InvariantViolation::KeysOutOfOrder { node } => {
+                write!(f, "node {} has keys out of order", node)
+            }
+            InvariantViolation::Underflow { node, keys, min } => {
+                write!(f, "node {} holds {} keys, below the minimum of {}", node, keys, min)
+            }
+            InvariantViolation::Overflow { node, keys, max } => {
+                write!(f, "node {} holds {} keys, above the maximum of {}", node, keys, max)
+            }
+            InvariantViolation::ChildCountMismatch { node, keys, children } => {
+                write!(f, "node {} has {} keys but {} children", node, keys, children)
+            }
+            InvariantViolation::WrongDepth { node, depth, height } => {
+                write!(f, "node {} is at depth {} in a tree of height {}", node, depth, height)
+            }
+            InvariantViolation::OutsideSeparators { node } => {
+                write!(f, "node {} holds a key outside its parent's separators", node)
+            }
+            InvariantViolation::StaleCount { node, child, recorded: Some(recorded), entries } => {
+                write!(
+                    f,
+                    "node {} records {} entries under child {}, which holds {}",
+                    node, recorded, child, entries
+                )
+            }
+            InvariantViolation::StaleCount { node, child, recorded: None, .. } => {
+                write!(f, "node {} records no entry count for child {}", node, child)
+            }
+            InvariantViolation::BrokenLeafChain { leaf } => {
+                write!(f, "leaf {} is linked to the wrong neighbours", leaf)
+            }
+            InvariantViolation::LenMismatch { len, entries } => {
+                write!(f, "len is {} but the leaves hold {} entries", len, entries)
+            }
+        }
+    }
+}
+
+impl std::error::Error for InvariantViolation {}
+
+/// Outcome of `BPlusTree::insert_with`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Upsert {
+    /// The key was absent and a new entry was added
+    Created,
+    /// The key was present and the value was merged into it
+    Merged,
+}
+
+/// Which neighbour `BPlusTree::nearest` returns when both are equally close
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Tie {
+    /// Prefer the smaller key
+    #[default]
+    Lower,
+    /// Prefer the larger key
+    Higher,
+}
+
+/// A view into a single key of the tree, created by `BPlusTree::entry`
+pub enum TreeEntry<'a, K, V, C = NaturalOrder> {
+    Occupied(OccupiedEntry<'a, K, V, C>),
+    Vacant(VacantEntry<'a, K, V, C>),
+}
+
+impl<'a, K: Clone, V, C: Comparator<K>> TreeEntry<'a, K, V, C> {
+    /// The key this entry refers to
+    pub fn key(&self) -> &K {
+        match self {
+            TreeEntry::Occupied(entry) => entry.key(),
+            TreeEntry::Vacant(entry) => entry.key(),
+        }
+    }
+
+    /// Insert `default` if the key is vacant and return the stored value
+    pub fn or_insert(self, default: V) -> &'a mut V {
+        self.or_insert_with(|| default)
+    }
+
+    /// Insert the result of `default` if the key is vacant and return the stored value
+    pub fn or_insert_with<F: FnOnce() -> V>(self, default: F) -> &'a mut V {
+        match self {
+            TreeEntry::Occupied(entry) => entry.into_mut(),
+            TreeEntry::Vacant(entry) => entry.insert(default()),
+        }
+    }
+
+    /// Insert the default value if the key is vacant and return the stored value
+    pub fn or_default(self) -> &'a mut V
+    where
+        V: Default,
+    {
+        self.or_insert_with(V::default)
+    }
+
+    /// Apply `f` to the stored value if the key is occupied
+    pub fn and_modify<F: FnOnce(&mut V)>(self, f: F) -> Self {
+        match self {
+            TreeEntry::Occupied(mut entry) => {
+                f(entry.get_mut());
+                TreeEntry::Occupied(entry)
+            }
+            vacant => vacant,
+        }
+    }
+}
+
+/// An entry for a key that is present in the tree
+pub struct OccupiedEntry<'a, K, V, C = NaturalOrder> {
+    tree: &'a mut BPlusTree<K, V, C>,
+    leaf: NodeId,
+    idx: usize,
+}
+
+impl<'a, K: Clone, V, C: Comparator<K>> OccupiedEntry<'a, K, V, C> {
+    fn slot(&self) -> &Entry<K, V> {
+        match &self.tree.nodes[self.leaf] {
+            Node::Leaf { entries, .. } => &entries[self.idx],
+            Node::Internal { .. } => unreachable!("entries live in leaves"),
+        }
+    }
+
+    fn slot_mut(&mut self) -> &mut Entry<K, V> {
+        match &mut self.tree.nodes[self.leaf] {
+            Node::Leaf { entries, .. } => &mut entries[self.idx],
+            Node::Internal { .. } => unreachable!("entries live in leaves"),
+        }
+    }
+
+    pub fn key(&self) -> &K {
+        &self.slot().key
+    }
+
+    pub fn get(&self) -> &V {
+        &self.slot().value
+    }
+
+    pub fn get_mut(&mut self) -> &mut V {
+        &mut self.slot_mut().value
+    }
+
+    /// Convert into a reference tied to the tree's borrow
+    pub fn into_mut(self) -> &'a mut V {
+        match &mut self.tree.nodes[self.leaf] {
+            Node::Leaf { entries, .. } => &mut entries[self.idx].value,
+            Node::Internal { .. } => unreachable!("entries live in leaves"),
+        }
+    }
+
+    /// Replace the stored value, returning the old one
+    pub fn insert(&mut self, value: V) -> V {
+        std::mem::replace(self.get_mut(), value)
+    }
+
+    /// Remove the entry from the tree, returning its value
+    pub fn remove(self) -> V {
+        let key = self.key().clone();
+        self.tree.remove(&key).expect("occupied entry is present")
+    }
+}
+
+/// An entry for a key that is absent from the tree
+pub struct VacantEntry<'a, K, V, C = NaturalOrder> {
+    tree: &'a mut BPlusTree<K, V, C>,
+    key: K,
+}
+
+impl<'a, K: Clone, V, C: Comparator<K>> VacantEntry<'a, K, V, C> {
+    pub fn key(&self) -> &K {
+        &self.key
+    }
+
+    /// Insert `value` under this entry's key and return a reference to it
+    ///
+    /// The insert reports where the entry landed, so no lookup follows it.
+    pub fn insert(self, value: V) -> &'a mut V {
+        let (leaf, idx) = self.tree.insert_entry(self.key, value);
+        OccupiedEntry {
+            tree: self.tree,
+            leaf,
+            idx,
+        }
+        .into_mut()
+    }
+}
+
+/// Iterator over `(&key, &value)` pairs, created by `BPlusTree::iter`
+///
+/// `front` and `back` are `(leaf, index)` cursors; `back` points one past the
+/// next entry to yield from the end. Iteration stops when they meet.
+pub struct Iter<'a, K, V> {
+    nodes: &'a [Node<K, V>],
+    front: (NodeId, usize),
+    back: (NodeId, usize),
+}
+
+impl<'a, K, V> Iterator for Iter<'a, K, V> {
+    type Item = (&'a K, &'a V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let nodes = self.nodes;
+        while self.front != self.back {
+            let (leaf, idx) = self.front;
+            let Node::Leaf { entries, next, .. } = &nodes[leaf] else {
+                return None;
+            };
+            if let Some(entry) = entries.get(idx) {
+                self.front.1 += 1;
+                return Some((&entry.key, &entry.value));
+            }
+            self.front = ((*next)?, 0);
+        }
+        None
+    }
+}
+
+impl<K, V> DoubleEndedIterator for Iter<'_, K, V> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        let nodes = self.nodes;
+        while self.front != self.back {
+            let (leaf, idx) = self.back;
+            let Node::Leaf { entries, prev, .. } = &nodes[leaf] else {
+                return None;
+            };
+            if idx > 0 {
+                self.back.1 -= 1;
+                let entry = &entries[idx - 1];
+                return Some((&entry.key, &entry.value));
+            }
+            let prev = (*prev)?;
+            self.back = (prev, nodes[prev].num_keys());
+        }
+        None
+    }
+}
+
+/// Iterator over keys, created by `BPlusTree::keys`
+pub struct Keys<'a, K, V>(Iter<'a, K, V>);
+
+impl<'a, K, V> Iterator for Keys<'a, K, V> {
+    type Item = &'a K;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.0.next().map(|(k, _)| k)
+    }
+}
+
+impl<K, V> DoubleEndedIterator for Keys<'_, K, V> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        self.0.next_back().map(|(k, _)| k)
+    }
+}
+
+/// Iterator over values, created by `BPlusTree::values`
+pub struct Values<'a, K, V>(Iter<'a, K, V>);
+
+impl<'a, K, V> Iterator for Values<'a, K, V> {
+    type Item = &'a V;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.0.next().map(|(_, v)| v)
+    }
+}
+
+impl<K, V> DoubleEndedIterator for Values<'_, K, V> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        self.0.next_back().map(|(_, v)| v)
+    }
+}
+
+/// Owning iterator over `(key, value)` pairs in ascending key order
+pub struct IntoIter<K, V> {
+    nodes: Vec<Node<K, V>>,
+    leaf: Option<NodeId>,
+    entries: std::vec::IntoIter<Entry<K, V>>,
+}
+
+impl<K, V> Iterator for IntoIter<K, V> {
+    type Item = (K, V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(entry) = self.entries.next() {
+                return Some((entry.key, entry.value));
+            }
+            let Node::Leaf { entries, next, .. } = &mut self.nodes[self.leaf?] else {
+                return None;
+            };
+            self.entries = std::mem::take(entries).into_iter();
+            self.leaf = *next;
+        }
+    }
+}
+
+/// Owning iterator over entries removed by `BPlusTree::drain` or
+/// `BPlusTree::drain_range`
+pub struct Drain<K, V>(std::vec::IntoIter<Entry<K, V>>);
+
+impl<K, V> Iterator for Drain<K, V> {
+    type Item = (K, V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.0.next().map(|e| (e.key, e.value))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.0.size_hint()
+    }
+}
+
+impl<K, V> DoubleEndedIterator for Drain<K, V> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        self.0.next_back().map(|e| (e.key, e.value))
+    }
+}
+
+impl<K, V> ExactSizeIterator for Drain<K, V> {}
+
+impl<'a, K: Clone, V, C: Comparator<K>> IntoIterator for &'a BPlusTree<K, V, C> {
+    type Item = (&'a K, &'a V);
+    type IntoIter = Iter<'a, K, V>;
+
+    fn into_iter(self) -> Iter<'a, K, V> {
+        self.iter()
+    }
+}
+
+impl<K: Clone, V, C: Comparator<K>> IntoIterator for BPlusTree<K, V, C> {
+    type Item = (K, V);
+    type IntoIter = IntoIter<K, V>;
+
+    fn into_iter(self) -> IntoIter<K, V> {
+        IntoIter {
+            leaf: Some(self.leftmost_leaf()),
+            nodes: self.nodes,
+            entries: Vec::new().into_iter(),
+        }
+    }
+}
+
+impl<K: Clone, V, C: Comparator<K> + Default> Default for BPlusTree<K, V, C> {
+    fn default() -> Self {
+        Self::with_comparator(C::default())
+    }
+}
+
+/// Collects into a tree with the default degree, bulk loading the entries
+///
+/// Input already in strictly ascending key order is packed directly; anything
+/// else goes through `insert_batch`, so the last value wins for repeated keys.
+impl<K: Clone, V, C: Comparator<K> + Default> FromIterator<(K, V)> for BPlusTree<K, V, C> {
+    fn from_iter<I: IntoIterator<Item = (K, V)>>(iter: I) -> Self {
+        let entries: Vec<(K, V)> = iter.into_iter().collect();
+        let mut tree = Self::default();
+        if entries.windows(2).all(|w| tree.cmp.compare(&w[0].0, &w[1].0).is_lt()) {
+            let entries = entries.into_iter().map(|(key, value)| Entry { key, value });
+            tree.rebuild(entries.collect());
+        } else {
+            tree.insert_batch(entries);
+        }
+        tree
+    }
+}
+
+impl<K: Clone, V, C: Comparator<K>> Extend<(K, V)> for BPlusTree<K, V, C> {
+    fn extend<I: IntoIterator<Item = (K, V)>>(&mut self, iter: I) {
+        for (key, value) in iter {
+            self.insert(key, value);
+        }
+    }
+}
+
+/// Trees are equal when they hold the same entries, regardless of degree or
+/// node layout
+impl<K: Clone + PartialEq, V: PartialEq, C: Comparator<K>> PartialEq for BPlusTree<K, V, C> {
+    fn eq(&self, other: &Self) -> bool {
+        self.len == other.len && self.iter().eq(other.iter())
+    }
+}
+
+impl<K: Clone + Eq, V: Eq, C: Comparator<K>> Eq for BPlusTree<K, V, C> {}
+
+impl<K: Clone + fmt::Debug, V: fmt::Debug, C: Comparator<K>> fmt::Debug for BPlusTree<K, V, C> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_map().entries(self.iter()).finish()
+    }
+}
+
+impl<K: Clone + fmt::Debug, V, C: Comparator<K>> fmt::Display for BPlusTree<K, V, C> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "B+ Tree(height={}, len={}, keys={:?})",
+            self.height,
+            self.len,
+            self.all_keys()
+        )
+    }
+}
+
+/// Trees serialize as their degree plus the entries in key order, and are
+/// bulk loaded on the way back in, so snapshots don't depend on node layout
+#[cfg(feature = "serde")]
+mod serde_impl {
+    use super::{BPlusTree, Comparator, Entry};
+    use serde::de::{Deserialize, Deserializer, Error};
+    use serde::ser::{Serialize, Serializer};
+
+    #[derive(serde::Serialize)]
+    #[serde(
+        rename = "BPlusTree",
+        bound = "K: Clone + Serialize, V: Serialize, C: Comparator<K>"
+    )]
+    struct SnapshotRef<'a, K, V, C> {
+        min_degree: usize,
+        entries: EntriesRef<'a, K, V, C>,
+    }
+
+    struct EntriesRef<'a, K, V, C>(&'a BPlusTree<K, V, C>);
+
+    #[derive(serde::Serialize)]
+    #[serde(rename = "Entry")]
+    struct EntryRef<'a, K, V> {
+        key: &'a K,
+        value: &'a V,
+    }
+
+    #[derive(serde::Deserialize)]
+    #[serde(rename = "BPlusTree")]
+    struct Snapshot<K, V> {
+        min_degree: usize,
+        entries: Vec<Entry<K, V>>,
+    }
+
+    impl<K, V, C> Serialize for EntriesRef<'_, K, V, C>
+    where
+        K: Clone + Serialize,
+        V: Serialize,
+        C: Comparator<K>,
+    {
+        fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+            serializer.collect_seq(self.0.iter().map(|(key, value)| EntryRef { key, value }))
+        }
+    }
+
+    /// The comparator itself is not serialized; entries are written in the
+    /// tree's own order
+    impl<K, V, C> Serialize for BPlusTree<K, V, C>
+    where
+        K: Clone + Serialize,
+        V: Serialize,
+        C: Comparator<K>,
+    {
+        fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+            SnapshotRef {
+                min_degree: self.min_degree,
+                entries: EntriesRef(self),
+            }
+            .serialize(serializer)
+        }
+    }
+
+    /// Rebuilds the tree with `C::default()`, which must order the entries the
+    /// same way as the comparator they were serialized with
+    impl<'de, K, V, C> Deserialize<'de> for BPlusTree<K, V, C>
+    where
+        K: Clone + Deserialize<'de>,
+        V: Deserialize<'de>,
+        C: Comparator<K> + Default,
+    {
+        fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+            let snapshot = Snapshot::<K, V>::deserialize(deserializer)?;
+            if snapshot.min_degree < 2 {
+                return Err(D::Error::custom(format!(
+                    "min_degree must be at least 2, got {}",
+                    snapshot.min_degree
+                )));
+            }
+            let cmp = C::default();
+            let ascending = |w: &[Entry<K, V>]| cmp.compare(&w[0].key, &w[1].key).is_lt();
+            if !snapshot.entries.windows(2).all(ascending) {
+                return Err(D::Error::custom("entries are not in strictly ascending key order"));
+            }
+            let mut tree = BPlusTree::with_degree_and_comparator(snapshot.min_degree, cmp);
+            tree.rebuild(snapshot.entries);
+            Ok(tree)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Build a tree of `key -> "value_{key}"` directly, bypassing insert
+    fn tree_of(keys: impl IntoIterator<Item = i32>) -> BPlusTree {
+        let mut tree = BPlusTree::new();
+        let entries = keys
+            .into_iter()
+            .map(|key| Entry {
+                key,
+                value: format!("value_{}", key),
+            })
+            .collect();
+        tree.rebuild(entries);
+        tree
+    }
+
+    /// Check `validate` and that iteration agrees in both directions
+    fn assert_invariants(tree: &BPlusTree) {
+        if let Err(violation) = tree.validate() {
+            panic!("{}", violation);
+        }
+
+        let forward: Vec<i32> = tree.keys().copied().collect();
+        let mut backward: Vec<i32> = tree.keys().rev().copied().collect();
+        backward.reverse();
+        assert!(forward.windows(2).all(|w| w[0] < w[1]));
+        assert_eq!(forward, backward);
+        assert_eq!(forward.len(), tree.len());
+    }
+
+    #[test]
+    fn test_insert_and_search() {
+        let mut tree = BPlusTree::new();
+        tree.insert(10, "ten".to_string());
+        tree.insert(20, "twenty".to_string());
+        tree.insert(5, "five".to_string());
+
+        assert_eq!(tree.search(&10), Some("ten".to_string()));
+        assert_eq!(tree.search(&20), Some("twenty".to_string()));
+        assert_eq!(tree.search(&5), Some("five".to_string()));
+        assert_eq!(tree.search(&100), None);
+    }
+
+    #[test]
+    fn test_range_query() {
+        let mut tree = BPlusTree::new();
+        for i in 1..=10 {
+            tree.insert(i * 10, format!("value_{}", i));
+        }
+
+        let result = tree.range_query(&25, &75);
+        assert!(!result.is_empty());
+    }
+
+    #[test]
+    fn test_range_query_walks_leaf_chain() {
+        let mut tree = BPlusTree::new();
+        for i in 1..=12 {
+            tree.insert(i * 10, format!("value_{}", i));
+        }
+
+        let keys: Vec<i32> = tree.range_query(&25, &95).into_iter().map(|(k, _)| k).collect();
+        assert_eq!(keys, vec![30, 40, 50, 60, 70, 80, 90]);
+        assert_eq!(tree.range_query(&1, &10), vec![(10, "value_1".to_string())]);
+        assert!(tree.range_query(&121, &200).is_empty());
+        assert!(tree.range_query(&50, &40).is_empty());
+    }
+
+    #[test]
+    fn test_len_and_is_empty() {
+        let mut tree = BPlusTree::new();
+        assert!(tree.is_empty());
+
+        for i in [7, 3, 11, 1, 9, 5, 12, 2] {
+            tree.insert(i, format!("value_{}", i));
+        }
+        tree.insert(3, "three".to_string());
+        assert_eq!(tree.len(), 8);
+        assert_eq!(tree.len(), tree.iter().count());
+
+        tree.remove(&3);
+        tree.remove(&4);
+        tree.pop_first();
+        tree.entry(20).or_insert("twenty".to_string());
+        assert_eq!(tree.len(), 7);
+        assert_eq!(
+            tree.to_string(),
+            "B+ Tree(height=2, len=7, keys=[2, 5, 7, 9, 11, 12, 20])"
+        );
+
+        while tree.pop_last().is_some() {}
+        assert!(tree.is_empty());
+    }
+
+    #[test]
+    fn test_iterators() {
+        let mut tree = BPlusTree::new();
+        for i in [8, 3, 11, 1, 9, 5, 12, 2] {
+            tree.insert(i, format!("value_{}", i));
+        }
+
+        let expected = vec![1, 2, 3, 5, 8, 9, 11, 12];
+        assert_eq!(tree.keys().copied().collect::<Vec<_>>(), expected);
+        assert_eq!(
+            tree.values().cloned().collect::<Vec<_>>(),
+            expected.iter().map(|k| format!("value_{}", k)).collect::<Vec<_>>()
+        );
+        assert_eq!(tree.iter().filter(|(k, _)| **k > 4).count(), 5);
+
+        let mut borrowed = Vec::new();
+        for (k, v) in &tree {
+            borrowed.push((*k, v.clone()));
+        }
+        let owned: Vec<(i32, String)> = tree.into_iter().collect();
+        assert_eq!(owned, borrowed);
+        assert_eq!(owned.len(), expected.len());
+    }
+
+    #[test]
+    fn test_reverse_iteration() {
+        let mut tree = BPlusTree::new();
+        for i in 1..=12 {
+            tree.insert(i, format!("value_{}", i));
+        }
+
+        let latest: Vec<i32> = tree.keys().rev().take(3).copied().collect();
+        assert_eq!(latest, vec![12, 11, 10]);
+        assert_eq!(
+            tree.keys().rev().copied().collect::<Vec<_>>(),
+            (1..=12).rev().collect::<Vec<_>>()
+        );
+        assert_eq!(tree.values().next_back(), Some(&"value_12".to_string()));
+
+        // Alternate ends until the cursors meet in the middle
+        let mut iter = tree.keys();
+        let mut seen = Vec::new();
+        while let (Some(a), b) = (iter.next(), iter.next_back()) {
+            seen.push(*a);
+            seen.extend(b.copied());
+        }
+        seen.sort();
+        assert_eq!(seen, (1..=12).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_iter_empty_tree() {
+        let tree: BPlusTree = BPlusTree::new();
+        assert_eq!(tree.iter().next(), None);
+        assert_eq!(tree.iter().next_back(), None);
+        assert_eq!(tree.into_iter().next(), None);
+    }
+
+    #[test]
+    fn test_lazy_range_bounds() {
+        let mut tree = BPlusTree::new();
+        for i in 1..=12 {
+            tree.insert(i * 10, format!("value_{}", i));
+        }
+        let keys = |r: Iter<i32, String>| r.map(|(k, _)| *k).collect::<Vec<_>>();
+
+        assert_eq!(keys(tree.range(30..=60)), vec![30, 40, 50, 60]);
+        assert_eq!(keys(tree.range(30..60)), vec![30, 40, 50]);
+        assert_eq!(keys(tree.range(25..65)), vec![30, 40, 50, 60]);
+        assert_eq!(keys(tree.range(..=20)), vec![10, 20]);
+        assert_eq!(keys(tree.range(105..)), vec![110, 120]);
+        assert_eq!(
+            keys(tree.range((Bound::Excluded(30), Bound::Excluded(60)))),
+            vec![40, 50]
+        );
+        assert_eq!(keys(tree.range(..)).len(), 12);
+        assert_eq!(
+            tree.range(30..=60).rev().map(|(k, _)| *k).collect::<Vec<_>>(),
+            vec![60, 50, 40, 30]
+        );
+        assert_eq!(tree.range(41..=49).next(), None);
+        assert!(tree.range_query(&60, &30).is_empty());
+        assert_eq!(tree.range((Bound::Excluded(30), Bound::Excluded(30))).next(), None);
+        assert_eq!(tree.range(200..).next_back(), None);
+    }
+
+    #[test]
+    fn test_leaf_links_survive_splits_and_merges() {
+        let mut tree = BPlusTree::new();
+        for i in [7, 3, 11, 1, 9, 5, 12, 2, 8, 4, 10, 6] {
+            tree.insert(i, format!("value_{}", i));
+        }
+        for i in [3, 4, 9] {
+            tree.remove(&i);
+        }
+
+        // Walk the chain forwards, then backwards from the last leaf
+        let mut forward = Vec::new();
+        let mut last = tree.leftmost_leaf();
+        let mut leaf = Some(last);
+        while let Some(Node::Leaf { entries, next, .. }) = leaf.map(|id| &tree.nodes[id]) {
+            forward.extend(entries.iter().map(|e| e.key));
+            last = leaf.unwrap();
+            leaf = *next;
+        }
+        let mut backward = Vec::new();
+        let mut leaf = Some(last);
+        while let Some(Node::Leaf { entries, prev, .. }) = leaf.map(|id| &tree.nodes[id]) {
+            backward.extend(entries.iter().rev().map(|e| e.key));
+            leaf = *prev;
+        }
+        backward.reverse();
+
+        assert_eq!(forward, vec![1, 2, 5, 6, 7, 8, 10, 11, 12]);
+        assert_eq!(backward, forward);
+    }
+
+    #[test]
+    fn test_contains_key() {
+        let mut tree = BPlusTree::new();
+        for i in 1..=12 {
+            tree.insert(i * 10, format!("value_{}", i));
+        }
+
+        assert!(tree.contains_key(&10));
+        assert!(tree.contains_key(&120));
+        assert!(!tree.contains_key(&15));
+        tree.remove(&60);
+        assert!(!tree.contains_key(&60));
+
+        let mut names = BPlusTree::new();
+        names.insert("alice".to_string(), 1);
+        assert!(names.contains_key("alice"));
+        assert!(!names.contains_key("bob"));
+    }
+
+    #[test]
+    fn test_get_mut() {
+        let mut tree = BPlusTree::new();
+        for i in 1..=12 {
+            tree.insert(i, format!("value_{}", i));
+        }
+
+        if let Some(value) = tree.get_mut(&7) {
+            value.push_str("_updated");
+        }
+        assert_eq!(tree.search(&7), Some("value_7_updated".to_string()));
+        assert_eq!(tree.get_mut(&13), None);
+    }
+
+    #[test]
+    fn test_entry_api() {
+        let mut tree: BPlusTree = BPlusTree::new();
+        for word in ["a", "b", "a", "c", "a", "b"] {
+            let key = word.as_bytes()[0] as i32;
+            tree.entry(key)
+                .and_modify(|count| count.push('|'))
+                .or_insert_with(|| "|".to_string());
+        }
+        assert_eq!(tree.search(&('a' as i32)), Some("|||".to_string()));
+        assert_eq!(tree.search(&('b' as i32)), Some("||".to_string()));
+        assert_eq!(tree.search(&('c' as i32)), Some("|".to_string()));
+
+        assert_eq!(*tree.entry(1).key(), 1);
+        tree.entry(1).or_default().push_str("new");
+        assert_eq!(tree.search(&1), Some("new".to_string()));
+
+        match tree.entry(1) {
+            TreeEntry::Occupied(mut entry) => {
+                assert_eq!(entry.get(), "new");
+                assert_eq!(entry.insert("newer".to_string()), "new");
+                assert_eq!(entry.remove(), "newer");
+            }
+            TreeEntry::Vacant(_) => panic!("key 1 should be occupied"),
+        }
+        assert!(matches!(tree.entry(1), TreeEntry::Vacant(_)));
+        assert_eq!(tree.entry(2).or_insert("two".to_string()), "two");
+
+        // The reference returned for a vacant key survives the splits its
+        // insertion causes
+        let mut tree = BPlusTree::with_degree(2);
+        for i in 0..200 {
+            let key = (i * 37) % 200;
+            let value = tree.entry(key).or_insert(String::new());
+            value.push_str(&key.to_string());
+        }
+        assert_invariants(&tree);
+        assert!(tree.iter().all(|(k, v)| *v == k.to_string()));
+    }
+
+    #[cfg(feature = "profile")]
+    #[test]
+    fn test_point_operations_are_profiled() {
+        let calls = |phase: Phase| {
+            profile::snapshot()
+                .into_iter()
+                .find(|(p, _, _)| *p == phase)
+                .map(|(_, calls, _)| calls)
+                .unwrap()
+        };
+        let mut tree = tree_of(0..100);
+        let mut assert_profiled = |name: &str, op: &mut dyn FnMut(&mut BPlusTree)| {
+            let (descend, leaf_search) = (calls(Phase::Descend), calls(Phase::LeafSearch));
+            op(&mut tree);
+            assert!(calls(Phase::Descend) > descend, "{} did not time its descent", name);
+            assert!(calls(Phase::LeafSearch) > leaf_search, "{} did not time its leaf", name);
+        };
+        assert_profiled("search", &mut |tree| assert!(tree.search(&7).is_some()));
+        assert_profiled("insert", &mut |tree| tree.insert(1_000, "new".to_string()));
+        assert_profiled("remove", &mut |tree| assert!(tree.remove(&50).is_some()));
+    }
+
+    #[test]
+    fn test_insert_grows_beyond_two_levels() {
+        for min_degree in [2, 3, 5] {
+            let mut ascending = BPlusTree::with_degree(min_degree);
+            let mut descending = BPlusTree::with_degree(min_degree);
+            let mut shuffled = BPlusTree::with_degree(min_degree);
+            for i in 0..1000 {
+                ascending.insert(i, format!("value_{}", i));
+                descending.insert(999 - i, format!("value_{}", 999 - i));
+                let key = (i * 7919) % 1000;
+                shuffled.insert(key, format!("value_{}", key));
+            }
+
+            for tree in [&ascending, &descending, &shuffled] {
+                assert_invariants(tree);
+                assert_eq!(tree.len(), 1000);
+                assert!(tree.height > 3);
+                assert!((0..1000).all(|i| tree.contains_key(&i)));
+            }
+        }
+
+        // Re-inserting a separator key must update it in place, not duplicate it
+        let mut tree = BPlusTree::with_degree(2);
+        for i in 0..200 {
+            tree.insert(i, "old".to_string());
+        }
+        for i in 0..200 {
+            tree.insert(i, "new".to_string());
+        }
+        assert_invariants(&tree);
+        assert_eq!(tree.len(), 200);
+        assert!(tree.values().all(|v| v == "new"));
+    }
+
+    #[test]
+    fn test_validate_reports_violations() {
+        let tree = tree_of(0..100);
+        assert_eq!(tree.validate(), Ok(()));
+        let first = tree.leftmost_leaf();
+
+        let mut swapped = tree_of(0..100);
+        if let Node::Leaf { entries, .. } = &mut swapped.nodes[first] {
+            entries.swap(0, 1);
+        }
+        assert_eq!(swapped.validate(), Err(InvariantViolation::KeysOutOfOrder { node: first }));
+
+        let mut emptied = tree_of(0..100);
+        if let Node::Leaf { entries, .. } = &mut emptied.nodes[first] {
+            entries.clear();
+        }
+        assert!(matches!(
+            emptied.validate(),
+            Err(InvariantViolation::Underflow { node, keys: 0, .. }) if node == first
+        ));
+
+        let mut misplaced = tree_of(0..100);
+        if let Node::Leaf { entries, .. } = &mut misplaced.nodes[first] {
+            entries.last_mut().unwrap().key = 1000;
+        }
+        assert_eq!(
+            misplaced.validate(),
+            Err(InvariantViolation::OutsideSeparators { node: first })
+        );
+
+        let mut unlinked = tree_of(0..100);
+        if let Node::Leaf { next, .. } = &mut unlinked.nodes[first] {
+            *next = None;
+        }
+        assert_eq!(unlinked.validate(), Err(InvariantViolation::BrokenLeafChain { leaf: first }));
+
+        let mut miscounted = tree_of(0..100);
+        miscounted.len += 1;
+        assert_eq!(
+            miscounted.validate(),
+            Err(InvariantViolation::LenMismatch { len: 101, entries: 100 })
+        );
+        assert_eq!(
+            miscounted.validate().unwrap_err().to_string(),
+            "len is 101 but the leaves hold 100 entries"
+        );
+    }
+
+    #[test]
+    fn test_stats() {
+        let empty: BPlusTree = BPlusTree::new();
+        let stats = empty.stats();
+        assert_eq!(stats.nodes_per_level, vec![1]);
+        assert_eq!(stats.leaf_fill_factor, 0.0);
+
+        let tree = tree_of(0..100);
+        let stats = tree.stats();
+        assert_eq!(stats.height, tree.height);
+        assert_eq!(stats.len, 100);
+        assert_eq!(stats.nodes_per_level.len(), tree.height);
+        assert_eq!(stats.nodes_per_level[0], 1);
+        assert!(stats.nodes_per_level.windows(2).all(|w| w[0] < w[1]));
+        assert_eq!(stats.node_count, stats.nodes_per_level.iter().sum::<usize>());
+        assert!(stats.leaf_fill_factor > 0.5 && stats.leaf_fill_factor <= 1.0);
+        assert!(stats.avg_entries_per_node >= (tree.min_degree - 1) as f64);
+        assert!(stats.memory_bytes > stats.node_count * std::mem::size_of::<Node<i32, String>>());
+
+        // Ascending inserts leave every left half of a split at minimum occupancy
+        let mut sequential = BPlusTree::new();
+        for i in 0..100 {
+            sequential.insert(i, format!("value_{}", i));
+        }
+        assert!(sequential.stats().leaf_fill_factor < stats.leaf_fill_factor);
+    }
+
+    #[test]
+    fn test_clone_eq_and_debug() {
+        let tree = tree_of(0..50);
+        let mut copy = tree.clone();
+        assert_eq!(copy, tree);
+
+        copy.insert(50, "value_50".to_string());
+        assert_ne!(copy, tree);
+        copy.remove(&50);
+        *copy.get_mut(&7).unwrap() = "changed".to_string();
+        assert_ne!(copy, tree);
+        assert_eq!(tree.search(&7), Some("value_7".to_string()));
+
+        // Layout and degree don't matter, only the entries
+        let mut wide = BPlusTree::with_degree(8);
+        for i in (0..50).rev() {
+            wide.insert(i, format!("value_{}", i));
+        }
+        assert_eq!(wide, tree);
+
+        assert_eq!(format!("{:?}", tree_of(1..3)), r#"{1: "value_1", 2: "value_2"}"#);
+    }
+
+    #[test]
+    fn test_from_iter_and_extend() {
+        let sorted: BPlusTree = (0..100).map(|i| (i, format!("value_{}", i))).collect();
+        assert_invariants(&sorted);
+        assert_eq!(sorted, tree_of(0..100));
+
+        let unsorted: BPlusTree<i32, &str> =
+            [(3, "c"), (1, "a"), (3, "z"), (2, "b")].into_iter().collect();
+        assert_eq!(unsorted.iter().collect::<Vec<_>>(), vec![(&1, &"a"), (&2, &"b"), (&3, &"z")]);
+
+        let mut tree = tree_of(0..50);
+        tree.extend((25..75).map(|i| (i, format!("value_{}", i))));
+        assert_invariants(&tree);
+        assert_eq!(tree, tree_of(0..75));
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_serde_round_trip() {
+        let mut tree = BPlusTree::with_degree(4);
+        for i in (0..100).rev() {
+            tree.insert(i, format!("value_{}", i));
+        }
+
+        let json = serde_json::to_string(&tree).unwrap();
+        assert!(json.starts_with(r#"{"min_degree":4,"entries":[{"key":0,"value":"value_0"}"#));
+        let restored: BPlusTree = serde_json::from_str(&json).unwrap();
+        assert_invariants(&restored);
+        assert_eq!(restored, tree);
+        assert_eq!(restored.min_degree(), 4);
+
+        let unsorted = r#"{"min_degree":3,"entries":[{"key":2,"value":"b"},{"key":1,"value":"a"}]}"#;
+        assert!(serde_json::from_str::<BPlusTree>(unsorted).is_err());
+        assert!(serde_json::from_str::<BPlusTree>(r#"{"min_degree":1,"entries":[]}"#).is_err());
+
+        let node = serde_json::to_string(&tree.nodes[tree.root]).unwrap();
+        let node: Node<i32, String> = serde_json::from_str(&node).unwrap();
+        assert_eq!(node.num_keys(), tree.nodes[tree.root].num_keys());
+    }
+
+    #[test]
+    fn test_rank_and_select() {
+        let mut tree = BPlusTree::new();
+        for i in (0..300).rev() {
+            tree.insert(i * 2, format!("value_{}", i * 2));
+        }
+        tree.delete_range(100..200);
+        tree.remove(&0);
+        assert_invariants(&tree);
+
+        let keys: Vec<i32> = tree.keys().copied().collect();
+        for (i, key) in keys.iter().enumerate() {
+            assert_eq!(tree.select(i).map(|(k, _)| k), Some(key));
+            assert_eq!(tree.rank(key), i);
+            assert_eq!(tree.rank(&(key + 1)), i + 1);
+        }
+        assert_eq!(tree.select(keys.len()), None);
+        assert_eq!(tree.rank(&-1), 0);
+        assert_eq!(tree.rank(&150), keys.partition_point(|&k| k < 150));
+        assert_eq!(tree.rank(&i32::MAX), tree.len());
+
+        let merged = tree.merge(tree_of(1000..1100), |_, ours, _| ours);
+        assert_invariants(&merged);
+        assert_eq!(merged.select(merged.len() - 1), Some((&1099, &"value_1099".to_string())));
+        assert_eq!(merged.rank(&1000), merged.len() - 100);
+    }
+
+    #[test]
+    fn test_nearest() {
+        let tree = tree_of((0..50).map(|i| i * 10));
+        let gap = |a: &i32, b: &i32| a.abs_diff(*b);
+
+        assert_eq!(tree.nearest(&30, Tie::Higher, gap).map(|(k, _)| *k), Some(30));
+        assert_eq!(tree.nearest(&32, Tie::Higher, gap).map(|(k, _)| *k), Some(30));
+        assert_eq!(tree.nearest(&37, Tie::Lower, gap).map(|(k, _)| *k), Some(40));
+        assert_eq!(tree.nearest(&35, Tie::Lower, gap).map(|(k, _)| *k), Some(30));
+        assert_eq!(tree.nearest(&35, Tie::Higher, gap).map(|(k, _)| *k), Some(40));
+        assert_eq!(tree.nearest(&-100, Tie::Lower, gap).map(|(k, _)| *k), Some(0));
+        assert_eq!(tree.nearest(&i32::MAX, Tie::Lower, gap).map(|(k, _)| *k), Some(490));
+
+        let empty: BPlusTree = BPlusTree::new();
+        assert_eq!(empty.nearest(&1, Tie::Lower, gap), None);
+    }
+
+    /// Deterministic xorshift generator for sampling tests
+    struct XorShift(u64);
+
+    impl RngCore for XorShift {
+        fn next_u32(&mut self) -> u32 {
+            self.next_u64() as u32
+        }
+
+        fn next_u64(&mut self) -> u64 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            self.0
+        }
+
+        fn fill_bytes(&mut self, dst: &mut [u8]) {
+            rand_core::impls::fill_bytes_via_next(self, dst)
+        }
+    }
+
+    #[test]
+    fn test_sample() {
+        let tree = tree_of(0..100);
+        let mut rng = XorShift(0x9E37_79B9_7F4A_7C15);
+
+        let picked: Vec<i32> = tree.sample(10, &mut rng).into_iter().map(|(k, _)| *k).collect();
+        assert_eq!(picked.len(), 10);
+        assert!(picked.windows(2).all(|w| w[0] < w[1]));
+        assert_eq!(tree.sample(500, &mut rng).len(), 100);
+        assert!(BPlusTree::<i32, String>::new().sample(3, &mut rng).is_empty());
+
+        // Every key should turn up at a roughly even rate
+        let mut hits = [0u32; 100];
+        for _ in 0..2000 {
+            for (k, _) in tree.sample(5, &mut rng) {
+                hits[*k as usize] += 1;
+            }
+        }
+        assert!(hits.iter().all(|&h| (50..150).contains(&h)), "{:?}", hits);
+    }
+
+    #[test]
+    fn test_with_comparator() {
+        let mut desc = BPlusTree::with_degree_and_comparator(2, |a: &i32, b: &i32| b.cmp(a));
+        for i in 0..200 {
+            desc.insert(i, i * 10);
+        }
+        // Ranges run in the comparator's order, from larger keys to smaller
+        desc.delete_range((Bound::Included(150), Bound::Excluded(100)));
+        desc.remove(&7);
+        assert_eq!(desc.validate(), Ok(()));
+
+        let keys: Vec<i32> = desc.keys().copied().collect();
+        let mut expected: Vec<i32> =
+            (0..200).filter(|&k| !(101..=150).contains(&k) && k != 7).collect();
+        expected.reverse();
+        assert_eq!(keys, expected);
+        assert_eq!(desc.first_key_value(), Some((&199, &1990)));
+        assert_eq!(desc.search(&42), Some(420));
+        let window: Vec<i32> =
+            desc.range((Bound::Included(60), Bound::Included(55))).map(|(k, _)| *k).collect();
+        assert_eq!(window, [60, 59, 58, 57, 56, 55]);
+        assert_eq!(desc.select(0), Some((&199, &1990)));
+        assert_eq!(desc.rank(&198), 1);
+
+        let right = desc.split_off(&50);
+        assert_eq!(right.keys().next(), Some(&50));
+        assert_eq!(desc.last_key_value().map(|(k, _)| *k), Some(51));
+        let desc = desc.merge(right, |_, ours, _| ours);
+        assert_eq!(desc.validate(), Ok(()));
+        assert_eq!(desc.len(), expected.len());
+
+        // Keys equal under the comparator are one key
+        let mut names = BPlusTree::with_comparator(|a: &String, b: &String| {
+            a.to_lowercase().cmp(&b.to_lowercase())
+        });
+        names.insert("Bob".to_string(), 1);
+        names.insert("alice".to_string(), 2);
+        names.insert("BOB".to_string(), 3);
+        assert_eq!(names.len(), 2);
+        assert_eq!(names.search(&"bob".to_string()), Some(3));
+        assert!(matches!(names.entry("ALICE".to_string()), TreeEntry::Occupied(_)));
+    }
+
+    #[test]
+    fn test_descending() {
+        let mut events: BPlusTree<u64, &str, Descending> = BPlusTree::descending();
+        for (ts, name) in [(1_000, "boot"), (3_000, "login"), (2_000, "sync"), (4_000, "logout")] {
+            events.insert(ts, name);
+        }
+
+        let names: Vec<&str> = events.values().copied().collect();
+        assert_eq!(names, ["logout", "login", "sync", "boot"]);
+        assert_eq!(events.first_key_value(), Some((&4_000, &"logout")));
+        let recent: Vec<u64> = events.range(..=2_000).map(|(ts, _)| *ts).collect();
+        assert_eq!(recent, [4_000, 3_000, 2_000]);
+        assert_eq!(events.range(2_500..).count(), 2);
+        assert_eq!(events.validate(), Ok(()));
+
+        let collected: BPlusTree<i32, (), Descending> = (0..50).map(|i| (i, ())).collect();
+        assert_eq!(collected.keys().next(), Some(&49));
+        assert_eq!(collected.validate(), Ok(()));
+    }
+
+    #[test]
+    fn test_insert_batch() {
+        let mut tree = BPlusTree::new();
+        for i in [5, 1, 9] {
+            tree.insert(i, format!("old_{}", i));
+        }
+
+        let mut batch: Vec<(i32, String)> =
+            (0..500).rev().map(|i| (i, format!("value_{}", i))).collect();
+        batch.push((9, "last".to_string()));
+        batch.push((700, "value_700".to_string()));
+        tree.insert_batch(batch);
+
+        assert_invariants(&tree);
+        assert_eq!(tree.len(), 501);
+        assert_eq!(tree.search(&5), Some("value_5".to_string()));
+        assert_eq!(tree.search(&9), Some("last".to_string()));
+        assert_eq!(tree.last_key_value().map(|(k, _)| *k), Some(700));
+
+        tree.insert_batch(Vec::new());
+        assert_eq!(tree.len(), 501);
+        tree.insert_batch(vec![(250, "x".to_string()), (1000, "y".to_string())]);
+        assert_invariants(&tree);
+        assert_eq!(tree.len(), 502);
+        assert_eq!(tree.search(&250), Some("x".to_string()));
+    }
+
+    #[test]
+    fn test_insert_batch_matches_single_inserts() {
+        let mut rng = XorShift(0x2545_F491_4F6C_DD1D);
+        for degree in [2, 3, 5] {
+            let mut batched = BPlusTree::with_degree(degree);
+            let mut single = BPlusTree::with_degree(degree);
+            for round in 0..40 {
+                let size = [1, 3, 20, 150][round % 4];
+                let batch: Vec<(i32, String)> = (0..size)
+                    .map(|_| {
+                        let key = (rng.next_u32() % 2000) as i32;
+                        (key, format!("{}_{}", key, round))
+                    })
+                    .collect();
+                for (key, value) in batch.clone() {
+                    single.insert(key, value);
+                }
+                batched.insert_batch(batch);
+                assert_invariants(&batched);
+            }
+            assert!(batched.iter().eq(single.iter()));
+        }
+    }
+
+    #[test]
+    fn test_insert_with() {
+        let mut counts = BPlusTree::new();
+        for word in ["b", "a", "b", "c", "b", "a"] {
+            counts.insert_with(word.to_string(), 1, |count, n| *count += n);
+        }
+        assert_eq!(counts.search("a"), Some(2));
+        assert_eq!(counts.search("b"), Some(3));
+        assert_eq!(counts.len(), 3);
+
+        let mut lists = BPlusTree::new();
+        assert_eq!(lists.insert_with(1, vec!['x'], |l, new| l.extend(new)), Upsert::Created);
+        assert_eq!(lists.insert_with(1, vec!['y', 'z'], |l, new| l.extend(new)), Upsert::Merged);
+        assert_eq!(lists.search(&1), Some(vec!['x', 'y', 'z']));
+    }
+
+    #[test]
+    fn test_first_and_last_key_value() {
+        let mut tree = BPlusTree::new();
+        assert_eq!(tree.first_key_value(), None);
+        assert_eq!(tree.last_key_value(), None);
+
+        for i in [7, 3, 11, 1, 9, 5, 12, 2] {
+            tree.insert(i, format!("value_{}", i));
+        }
+        assert_eq!(tree.first_key_value(), Some((&1, &"value_1".to_string())));
+        assert_eq!(tree.last_key_value(), Some((&12, &"value_12".to_string())));
+
+        tree.remove(&1);
+        tree.remove(&12);
+        assert_eq!(tree.first_key_value().map(|(k, _)| *k), Some(2));
+        assert_eq!(tree.last_key_value().map(|(k, _)| *k), Some(11));
+    }
+
+    #[test]
+    fn test_pop_first_and_last() {
+        let mut tree = BPlusTree::new();
+        for i in [7, 3, 11, 1, 9, 5, 12, 2, 8, 4, 10, 6] {
+            tree.insert(i, format!("value_{}", i));
+        }
+
+        assert_eq!(tree.pop_first(), Some((1, "value_1".to_string())));
+        assert_eq!(tree.pop_last(), Some((12, "value_12".to_string())));
+
+        let mut drained = Vec::new();
+        while let Some((k, _)) = tree.pop_first() {
+            drained.push(k);
+            if let Some((k, _)) = tree.pop_last() {
+                drained.push(k);
+            }
+        }
+        assert_eq!(drained, vec![2, 11, 3, 10, 4, 9, 5, 8, 6, 7]);
+        assert_eq!(tree.height, 1);
+        assert_eq!(tree.pop_first(), None);
+        assert_eq!(tree.pop_last(), None);
+    }
+
+    #[test]
+    fn test_floor_and_ceiling() {
+        let mut tree = BPlusTree::new();
+        for i in 1..=12 {
+            tree.insert(i * 10, format!("value_{}", i));
+        }
+        let key = |e: Option<(&i32, &String)>| e.map(|(k, _)| *k);
+
+        assert_eq!(key(tree.floor(&45)), Some(40));
+        assert_eq!(key(tree.floor(&40)), Some(40));
+        assert_eq!(key(tree.floor(&5)), None);
+        assert_eq!(key(tree.floor(&500)), Some(120));
+
+        assert_eq!(key(tree.ceiling(&45)), Some(50));
+        assert_eq!(key(tree.ceiling(&50)), Some(50));
+        assert_eq!(key(tree.ceiling(&5)), Some(10));
+        assert_eq!(key(tree.ceiling(&121)), None);
+    }
+
+    #[test]
+    fn test_prefix_range() {
+        let mut tree = BPlusTree::new();
+        for word in ["app", "apple", "apply", "apt", "banana", "ap", "b", "äpfel", "a"] {
+            tree.insert(word.to_string(), word.len());
+        }
+        let keys = |r: Iter<String, usize>| r.map(|(k, _)| k.clone()).collect::<Vec<_>>();
+
+        assert_eq!(keys(tree.prefix_range("app")), vec!["app", "apple", "apply"]);
+        assert_eq!(keys(tree.prefix_range("ap")), vec!["ap", "app", "apple", "apply", "apt"]);
+        assert_eq!(keys(tree.prefix_range("b")), vec!["b", "banana"]);
+        assert_eq!(keys(tree.prefix_range("ä")), vec!["äpfel"]);
+        assert!(keys(tree.prefix_range("c")).is_empty());
+        assert_eq!(tree.prefix_range("").count(), 9);
+        assert_eq!(tree.prefix_range("app").next_back().map(|(_, v)| *v), Some(5));
+    }
+
+    #[test]
+    fn test_byte_keys() {
+        let mut tree = BytesTree::with_degree(2);
+        let ids = [[0, 1, 0xff, 2], [0, 1, 0xff, 0xff], [0, 2, 0, 0], [0xff; 4]];
+        for (i, id) in ids.iter().enumerate() {
+            tree.insert(id.to_vec(), vec![i as u8; 3]);
+        }
+        tree.insert(b"\x00\x01".to_vec(), b"\x80\xff".to_vec());
+        assert_eq!(tree.validate(), Ok(()));
+
+        assert_eq!(tree.search(&[0u8, 2, 0, 0][..]), Some(vec![2; 3]));
+        assert_eq!(tree.get_mut(&b"\x00\x01"[..]).map(|v| v.len()), Some(2));
+        assert!(!tree.contains_key(&[0u8, 1, 0xff][..]));
+
+        let keys = |iter: Iter<'_, Vec<u8>, Vec<u8>>| -> Vec<Vec<u8>> {
+            iter.map(|(k, _)| k.clone()).collect()
+        };
+        assert_eq!(keys(tree.prefix_range(&[0, 1])).len(), 3);
+        assert_eq!(keys(tree.prefix_range(&[0, 1, 0xff])), [ids[0].to_vec(), ids[1].to_vec()]);
+        assert_eq!(keys(tree.prefix_range(&[0xff])), [vec![0xff; 4]]);
+        assert_eq!(tree.prefix_range(&[]).count(), 5);
+        assert_eq!(tree.remove(&[0xffu8; 4][..]), Some(vec![3; 3]));
+        assert_eq!(tree.validate(), Ok(()));
+    }
+
+    #[test]
+    fn test_bytes_successor() {
+        assert_eq!(bytes_successor(&[1, 2, 3]), Some(vec![1, 2, 4]));
+        assert_eq!(bytes_successor(&[1, 0xff, 0xff]), Some(vec![2]));
+        assert_eq!(bytes_successor(&[0xff]), None);
+        assert_eq!(bytes_successor(&[]), None);
+    }
+
+    #[test]
+    fn test_prefix_successor() {
+        assert_eq!(prefix_successor("abc"), Some("abd".to_string()));
+        assert_eq!(prefix_successor("a\u{10FFFF}"), Some("b".to_string()));
+        assert_eq!(prefix_successor("\u{D7FF}"), Some("\u{E000}".to_string()));
+        assert_eq!(prefix_successor("\u{10FFFF}"), None);
+        assert_eq!(prefix_successor(""), None);
+    }
+
+    #[test]
+    fn test_clear_and_retain() {
+        let mut tree = BPlusTree::new();
+        for i in [7, 3, 11, 1, 9, 5, 12, 2, 8, 4, 10, 6] {
+            tree.insert(i, format!("value_{}", i));
+        }
+
+        tree.retain(|k, v| {
+            v.push('!');
+            k % 3 != 0
+        });
+        assert_eq!(tree.all_keys(), vec![1, 2, 4, 5, 7, 8, 10, 11]);
+        assert_eq!(tree.len(), 8);
+        assert_eq!(tree.search(&4), Some("value_4!".to_string()));
+        assert_eq!(
+            tree.keys().rev().copied().collect::<Vec<_>>(),
+            vec![11, 10, 8, 7, 5, 4, 2, 1]
+        );
+
+        // The rebuilt tree still rebalances correctly on removal
+        for i in [1, 2, 4, 5] {
+            assert_eq!(tree.remove(&i), Some(format!("value_{}!", i)));
+        }
+        assert_eq!(tree.all_keys(), vec![7, 8, 10, 11]);
+
+        tree.retain(|_, _| true);
+        assert_eq!(tree.len(), 4);
+        tree.clear();
+        assert!(tree.is_empty());
+        assert_eq!(tree.iter().next(), None);
+        tree.insert(1, "one".to_string());
+        assert_eq!(tree.all_keys(), vec![1]);
+    }
+
+    #[test]
+    fn test_rebuild_meets_minimum_occupancy() {
+        for n in [0, 1, 5, 6, 9, 30, 200] {
+            let mut tree = BPlusTree::new();
+            let entries = (0..n)
+                .map(|key| Entry {
+                    key,
+                    value: key.to_string(),
+                })
+                .collect();
+            tree.rebuild(entries);
+
+            assert_eq!(tree.len(), n as usize);
+            assert_eq!(tree.all_keys(), (0..n).collect::<Vec<_>>());
+            for (id, node) in tree.nodes.iter().enumerate() {
+                assert!(id == tree.root || !node.is_underflow(3), "n={} node={}", n, id);
+                assert!(node.num_keys() < 2 * 3 - 1);
+            }
+            for key in 0..n {
+                assert_eq!(tree.remove(&key), Some(key.to_string()));
+            }
+            assert!(tree.is_empty());
+            assert_eq!(tree.height, 1);
+        }
+    }
+
+    #[test]
+    fn test_delete_range() {
+        let mut tree = tree_of(0..200);
+        assert_eq!(tree.delete_range(50..150), 100);
+        assert_invariants(&tree);
+        assert_eq!(tree.range(45..155).count(), 10);
+        assert!(!tree.contains_key(&50) && tree.contains_key(&150));
+
+        assert_eq!(tree.delete_range(..=10), 11);
+        assert_eq!(tree.delete_range((Bound::Excluded(180), Bound::Unbounded)), 19);
+        assert_invariants(&tree);
+        assert_eq!(tree.first_key_value().map(|(k, _)| *k), Some(11));
+        assert_eq!(tree.last_key_value().map(|(k, _)| *k), Some(180));
+
+        assert_eq!(tree.delete_range(60..70), 0);
+        assert_eq!(tree.delete_range(170..170), 0);
+        for key in [11, 12, 13, 180, 179] {
+            assert_eq!(tree.remove(&key), Some(format!("value_{}", key)));
+        }
+        assert_invariants(&tree);
+
+        let remaining = tree.len();
+        assert_eq!(tree.delete_range(..), remaining);
+        assert!(tree.is_empty());
+        assert_eq!(tree.height, 1);
+    }
+
+    #[test]
+    fn test_drain_and_drain_range() {
+        let mut tree = tree_of(0..100);
+
+        let drained: Vec<(i32, String)> = tree.drain_range(20..=60).collect();
+        assert_eq!(drained.len(), 41);
+        assert_eq!(drained[0], (20, "value_20".to_string()));
+        assert!(drained.windows(2).all(|w| w[0].0 + 1 == w[1].0));
+        assert_invariants(&tree);
+        assert_eq!(tree.len(), 59);
+
+        // Dropping the iterator early still removes the whole range
+        assert_eq!(tree.drain_range(..10).next_back(), Some((9, "value_9".to_string())));
+        assert_eq!(tree.first_key_value().map(|(k, _)| *k), Some(10));
+        assert_eq!(tree.drain_range(30..40).len(), 0);
+
+        let mut other = BPlusTree::new();
+        for (k, v) in tree.drain_range(95..) {
+            other.insert(k, v);
+        }
+        assert_eq!(other.all_keys(), vec![95, 96, 97, 98, 99]);
+
+        let rest: Vec<i32> = tree.drain().map(|(k, _)| k).collect();
+        assert_eq!(rest, (10..20).chain(61..95).collect::<Vec<_>>());
+        assert!(tree.is_empty());
+        assert_eq!(tree.drain().next(), None);
+    }
+
+    #[test]
+    fn test_split_off() {
+        for at in [-5, 0, 1, 57, 100, 199, 200, 250] {
+            let mut left = tree_of(0..200);
+            let right = left.split_off(&at);
+            assert_invariants(&left);
+            assert_invariants(&right);
+            assert_eq!(left.all_keys(), (0..at.clamp(0, 200)).collect::<Vec<_>>());
+            assert_eq!(right.all_keys(), (at.clamp(0, 200)..200).collect::<Vec<_>>());
+
+            let rejoined = left.merge(right, |_, _, _| unreachable!());
+            assert_eq!(rejoined.len(), 200);
+        }
+
+        let mut names = BPlusTree::new();
+        for name in ["ada", "bob", "cy", "dee"] {
+            names.insert(name.to_string(), ());
+        }
+        let tail = names.split_off("c");
+        assert_eq!(names.all_keys(), vec!["ada", "bob"]);
+        assert_eq!(tail.all_keys(), vec!["cy", "dee"]);
+    }
+
+    #[test]
+    fn test_merge_disjoint_trees() {
+        let no_conflicts = |_: &i32, _: String, _: String| -> String { panic!("keys are disjoint") };
+        for (left, right) in [(0..3, 3..200), (0..200, 200..203), (0..60, 60..120), (0..1, 1..2)] {
+            let expected: Vec<i32> = left.clone().chain(right.clone()).collect();
+
+            let merged = tree_of(left.clone()).merge(tree_of(right.clone()), no_conflicts);
+            assert_invariants(&merged);
+            assert_eq!(merged.all_keys(), expected);
+
+            let merged = tree_of(right).merge(tree_of(left), no_conflicts);
+            assert_invariants(&merged);
+            assert_eq!(merged.all_keys(), expected);
+        }
+
+        let mut merged = tree_of(0..20).merge(BPlusTree::new(), no_conflicts);
+        assert_eq!(merged.len(), 20);
+        merged = BPlusTree::new().merge(merged, no_conflicts);
+        assert_eq!(merged.len(), 20);
+        for key in 0..20 {
+            assert_eq!(merged.remove(&key), Some(format!("value_{}", key)));
+        }
+        assert!(merged.is_empty());
+    }
+
+    #[test]
+    fn test_merge_overlapping_trees() {
+        let mut left = tree_of((0..40).step_by(2));
+        left.insert(1, "left".to_string());
+        let right = tree_of((0..40).step_by(3));
+
+        let merged = left.merge(right, |k, ours, theirs| format!("{}:{}+{}", k, ours, theirs));
+        assert_invariants(&merged);
+        assert_eq!(merged.len(), 28);
+        assert_eq!(merged.search(&6), Some("6:value_6+value_6".to_string()));
+        assert_eq!(merged.search(&1), Some("left".to_string()));
+        assert_eq!(merged.search(&9), Some("value_9".to_string()));
+    }
+
+    #[test]
+    fn test_delete_range_every_window() {
+        for start in (0..60).step_by(7) {
+            for end in (start..=60).step_by(5) {
+                let mut tree = tree_of(0..60);
+                assert_eq!(tree.delete_range(start..end), (end - start) as usize);
+                assert_invariants(&tree);
+                assert_eq!(
+                    tree.all_keys(),
+                    (0..start).chain(end..60).collect::<Vec<_>>()
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_with_degree() {
+        for min_degree in [2, 4, 16] {
+            let mut tree = BPlusTree::with_degree(min_degree);
+            tree.rebuild((0..300).map(|key| Entry { key, value: key.to_string() }).collect());
+            assert_invariants(&tree);
+            assert_eq!(tree.min_degree(), min_degree);
+
+            tree.delete_range(40..260);
+            assert_invariants(&tree);
+            let tail = tree.split_off(&20);
+            assert_eq!(tail.min_degree(), min_degree);
+            assert_invariants(&tail);
+            for key in 0..20 {
+                assert_eq!(tree.remove(&key), Some(key.to_string()));
+            }
+            assert_invariants(&tree);
+        }
+
+        let mut wide = BPlusTree::with_degree(16);
+        for i in (0..100).rev() {
+            wide.insert(i, format!("value_{}", i));
+        }
+        assert_eq!(wide.height, 2);
+        assert_invariants(&wide);
+
+        // Differing degrees merge through the leaf streams into this tree's shape
+        let merged = wide.merge(tree_of(100..150), |_, _, _| unreachable!());
+        assert_eq!(merged.min_degree(), 16);
+        assert_invariants(&merged);
+        assert_eq!(merged.len(), 150);
+    }
+
+    #[test]
+    #[should_panic(expected = "min_degree must be at least 2")]
+    fn test_with_degree_rejects_degree_below_two() {
+        let _: BPlusTree = BPlusTree::with_degree(1);
+    }
+
+    #[test]
+    fn test_remove_from_leaf_root() {
+        let mut tree = BPlusTree::new();
+        tree.insert(1, "one".to_string());
+        tree.insert(2, "two".to_string());
+
+        assert_eq!(tree.remove(&1), Some("one".to_string()));
+        assert_eq!(tree.remove(&1), None);
+        assert_eq!(tree.search(&1), None);
+        assert_eq!(tree.all_keys(), vec![2]);
+    }
+
+    #[test]
+    fn test_remove_borrows_and_merges() {
+        let mut tree = BPlusTree::new();
+        for i in 1..=12 {
+            tree.insert(i, format!("value_{}", i));
+        }
+        assert_eq!(tree.height, 2);
+
+        for i in [1, 2, 12, 11, 6] {
+            assert_eq!(tree.remove(&i), Some(format!("value_{}", i)));
+            assert_eq!(tree.search(&i), None);
+        }
+        assert_eq!(tree.all_keys(), vec![3, 4, 5, 7, 8, 9, 10]);
+        for k in tree.all_keys() {
+            assert_eq!(tree.search(&k), Some(format!("value_{}", k)));
+        }
+    }
+
+    #[test]
+    fn test_remove_all_collapses_root() {
+        let mut tree = BPlusTree::new();
+        for i in 1..=12 {
+            tree.insert(i, format!("value_{}", i));
+        }
+
+        for i in (1..=12).rev() {
+            assert_eq!(tree.remove(&i), Some(format!("value_{}", i)));
+        }
+        assert_eq!(tree.height, 1);
+        assert!(tree.all_keys().is_empty());
+        assert_eq!(tree.remove(&5), None);
     }
 }
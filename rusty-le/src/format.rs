@@ -0,0 +1,209 @@
+use arrow::array::RecordBatch;
+use arrow::error::ArrowError;
+use arrow::util::display::{ArrayFormatter, FormatOptions};
+use std::io::Write;
+use std::str::FromStr;
+
+/// Widest a table cell may be before it is truncated
+pub const DEFAULT_MAX_WIDTH: usize = 32;
+
+/// How query results are written out
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum OutputFormat {
+    /// Aligned unicode table
+    #[default]
+    Table,
+    /// JSON array of row objects
+    Json,
+    /// CSV with a header row
+    Csv,
+    /// Arrow IPC stream, for piping into other Arrow tools
+    Arrow,
+}
+
+impl FromStr for OutputFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "table" => Ok(OutputFormat::Table),
+            "json" => Ok(OutputFormat::Json),
+            "csv" => Ok(OutputFormat::Csv),
+            "arrow" => Ok(OutputFormat::Arrow),
+            other => Err(format!(
+                "unknown format '{}', expected one of: table, json, csv, arrow",
+                other
+            )),
+        }
+    }
+}
+
+/// Write batches sharing one schema to `out` in the given format
+pub fn write_batches<W: Write>(
+    mut out: W,
+    batches: &[RecordBatch],
+    format: OutputFormat,
+) -> Result<(), ArrowError> {
+    match format {
+        OutputFormat::Table => {
+            let table = render_table(batches, DEFAULT_MAX_WIDTH)?;
+            writeln!(out, "{}", table)?;
+        }
+        OutputFormat::Json => {
+            let mut writer = arrow::json::ArrayWriter::new(&mut out);
+            for batch in batches {
+                writer.write(batch)?;
+            }
+            writer.finish()?;
+            writeln!(out)?;
+        }
+        OutputFormat::Csv => {
+            let mut writer = arrow::csv::Writer::new(&mut out);
+            for batch in batches {
+                writer.write(batch)?;
+            }
+        }
+        OutputFormat::Arrow => {
+            let Some(first) = batches.first() else {
+                return Ok(());
+            };
+            let mut writer = arrow::ipc::writer::StreamWriter::try_new(&mut out, &first.schema())?;
+            for batch in batches {
+                writer.write(batch)?;
+            }
+            writer.finish()?;
+        }
+    }
+    Ok(())
+}
+
+/// Render batches as an aligned unicode table, truncating cells wider than
+/// `max_width` characters
+pub fn render_table(batches: &[RecordBatch], max_width: usize) -> Result<String, ArrowError> {
+    let Some(first) = batches.first() else {
+        return Ok(String::from("(no rows)"));
+    };
+
+    let header: Vec<String> = first
+        .schema()
+        .fields()
+        .iter()
+        .map(|f| truncate(f.name(), max_width))
+        .collect();
+
+    let options = FormatOptions::default().with_null("NULL");
+    let mut rows: Vec<Vec<String>> = Vec::new();
+    for batch in batches {
+        let formatters = batch
+            .columns()
+            .iter()
+            .map(|c| ArrayFormatter::try_new(c.as_ref(), &options))
+            .collect::<Result<Vec<_>, _>>()?;
+        for row in 0..batch.num_rows() {
+            rows.push(
+                formatters
+                    .iter()
+                    .map(|f| truncate(&f.value(row).to_string(), max_width))
+                    .collect(),
+            );
+        }
+    }
+
+    let mut widths: Vec<usize> = header.iter().map(|h| h.chars().count()).collect();
+    for row in &rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.chars().count());
+        }
+    }
+
+    let border = |left: &str, mid: &str, right: &str| {
+        let segments: Vec<String> = widths.iter().map(|w| "─".repeat(w + 2)).collect();
+        format!("{}{}{}", left, segments.join(mid), right)
+    };
+    let line = |cells: &[String]| {
+        let padded: Vec<String> = cells
+            .iter()
+            .zip(&widths)
+            .map(|(c, w)| format!(" {}{} ", c, " ".repeat(w - c.chars().count())))
+            .collect();
+        format!("│{}│", padded.join("│"))
+    };
+
+    let mut lines = vec![border("┌", "┬", "┐"), line(&header), border("├", "┼", "┤")];
+    lines.extend(rows.iter().map(|r| line(r)));
+    lines.push(border("└", "┴", "┘"));
+    lines.push(format!("{} row(s)", rows.len()));
+    Ok(lines.join("\n"))
+}
+
+fn truncate(value: &str, max_width: usize) -> String {
+    if value.chars().count() <= max_width {
+        return value.to_string();
+    }
+    let mut truncated: String = value.chars().take(max_width.saturating_sub(1)).collect();
+    truncated.push('…');
+    truncated
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use arrow::array::{Int32Array, StringArray};
+    use arrow::datatypes::{DataType, Field, Schema};
+    use std::sync::Arc;
+
+    fn batch() -> RecordBatch {
+        let schema = Arc::new(Schema::new(vec![
+            Field::new("id", DataType::Int32, false),
+            Field::new("name", DataType::Utf8, true),
+        ]));
+        RecordBatch::try_new(
+            schema,
+            vec![
+                Arc::new(Int32Array::from(vec![1, 22])),
+                Arc::new(StringArray::from(vec![Some("a very long name"), None])),
+            ],
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_render_table_aligns_and_truncates() {
+        let table = render_table(&[batch()], 8).unwrap();
+        let expected = [
+            "┌────┬──────────┐",
+            "│ id │ name     │",
+            "├────┼──────────┤",
+            "│ 1  │ a very … │",
+            "│ 22 │ NULL     │",
+            "└────┴──────────┘",
+            "2 row(s)",
+        ]
+        .join("\n");
+        assert_eq!(table, expected);
+    }
+
+    #[test]
+    fn test_write_csv_and_json() {
+        let mut csv = Vec::new();
+        write_batches(&mut csv, &[batch()], OutputFormat::Csv).unwrap();
+        assert_eq!(
+            String::from_utf8(csv).unwrap(),
+            "id,name\n1,a very long name\n22,\n"
+        );
+
+        let mut json = Vec::new();
+        write_batches(&mut json, &[batch()], OutputFormat::Json).unwrap();
+        assert_eq!(
+            String::from_utf8(json).unwrap(),
+            "[{\"id\":1,\"name\":\"a very long name\"},{\"id\":22}]\n"
+        );
+    }
+
+    #[test]
+    fn test_parse_format() {
+        assert_eq!("JSON".parse(), Ok(OutputFormat::Json));
+        assert_eq!("arrow".parse(), Ok(OutputFormat::Arrow));
+        assert!("xml".parse::<OutputFormat>().is_err());
+    }
+}
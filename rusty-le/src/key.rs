@@ -0,0 +1,308 @@
+use arrow::array::{Array, AsArray, Datum};
+use arrow::datatypes::{
+    DataType, Float32Type, Float64Type, Int16Type, Int32Type, Int64Type, Int8Type, TimeUnit,
+    TimestampMicrosecondType, TimestampMillisecondType, TimestampNanosecondType,
+    TimestampSecondType, UInt16Type, UInt32Type, UInt64Type, UInt8Type,
+};
+use arrow::error::ArrowError;
+use chrono::{DateTime, NaiveDateTime, Utc};
+use std::cmp::Ordering;
+use std::fmt;
+
+/// A type the tree can be keyed by that can also be read out of Arrow data
+///
+/// Conversions are checked: an Arrow value is only accepted if it converts to
+/// the key type without loss, so an `Int64` column can key a `BPlusTree<i8, _>`
+/// as long as every value fits in an `i8`.
+pub trait Key: Ord + Clone {
+    /// Read the value at `row` of `array` as a key
+    ///
+    /// Fails if the row is null or out of bounds, the array's type cannot
+    /// hold this kind of key, or the value does not fit.
+    fn from_arrow(array: &dyn Array, row: usize) -> Result<Self, ArrowError>;
+
+    /// Read a key from an Arrow scalar such as `Scalar<Int64Array>`
+    fn from_scalar(scalar: &dyn Datum) -> Result<Self, ArrowError> {
+        let (array, is_scalar) = scalar.get();
+        if !is_scalar {
+            return Err(ArrowError::InvalidArgumentError(format!(
+                "expected a scalar, got an array of {} values",
+                array.len()
+            )));
+        }
+        Self::from_arrow(array, 0)
+    }
+}
+
+/// Read every value of `array` as a key, failing on the first bad row
+pub fn keys_from_arrow<K: Key>(array: &dyn Array) -> Result<Vec<K>, ArrowError> {
+    (0..array.len()).map(|row| K::from_arrow(array, row)).collect()
+}
+
+/// `f64` with a total order, so floating-point values can be used as keys
+///
+/// Ordering follows `f64::total_cmp`: `-0.0` sorts before `0.0` and NaNs
+/// sort after every other value (negative NaNs before everything), each NaN
+/// bit pattern being a distinct key.
+#[derive(Clone, Copy, Debug, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(transparent))]
+pub struct OrderedF64(pub f64);
+
+impl PartialEq for OrderedF64 {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for OrderedF64 {}
+
+impl PartialOrd for OrderedF64 {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for OrderedF64 {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.0.total_cmp(&other.0)
+    }
+}
+
+impl From<f64> for OrderedF64 {
+    fn from(value: f64) -> Self {
+        OrderedF64(value)
+    }
+}
+
+impl From<OrderedF64> for f64 {
+    fn from(value: OrderedF64) -> Self {
+        value.0
+    }
+}
+
+impl fmt::Display for OrderedF64 {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(&self.0, f)
+    }
+}
+
+/// Fail unless `row` holds a non-null value of `array`
+fn check_row(array: &dyn Array, row: usize) -> Result<(), ArrowError> {
+    if row >= array.len() {
+        return Err(ArrowError::InvalidArgumentError(format!(
+            "row {} out of bounds for an array of {} values",
+            row,
+            array.len()
+        )));
+    }
+    if array.is_null(row) {
+        return Err(ArrowError::InvalidArgumentError(format!(
+            "row {} is null and cannot be used as a key",
+            row
+        )));
+    }
+    Ok(())
+}
+
+fn unsupported(data_type: &DataType, key: &str) -> ArrowError {
+    ArrowError::CastError(format!("cannot read a {} key from {}", key, data_type))
+}
+
+/// Widen the integer at `row` so it can be narrowed to any key type
+fn integer_at(array: &dyn Array, row: usize, key: &str) -> Result<i128, ArrowError> {
+    check_row(array, row)?;
+    let value = match array.data_type() {
+        DataType::Int8 => array.as_primitive::<Int8Type>().value(row) as i128,
+        DataType::Int16 => array.as_primitive::<Int16Type>().value(row) as i128,
+        DataType::Int32 => array.as_primitive::<Int32Type>().value(row) as i128,
+        DataType::Int64 => array.as_primitive::<Int64Type>().value(row) as i128,
+        DataType::UInt8 => array.as_primitive::<UInt8Type>().value(row) as i128,
+        DataType::UInt16 => array.as_primitive::<UInt16Type>().value(row) as i128,
+        DataType::UInt32 => array.as_primitive::<UInt32Type>().value(row) as i128,
+        DataType::UInt64 => array.as_primitive::<UInt64Type>().value(row) as i128,
+        other => return Err(unsupported(other, key)),
+    };
+    Ok(value)
+}
+
+macro_rules! integer_key {
+    ($($t:ty),*) => {
+        $(
+            impl Key for $t {
+                fn from_arrow(array: &dyn Array, row: usize) -> Result<Self, ArrowError> {
+                    let value = integer_at(array, row, stringify!($t))?;
+                    <$t>::try_from(value).map_err(|_| {
+                        ArrowError::CastError(format!(
+                            "{} does not fit in a {} key",
+                            value,
+                            stringify!($t)
+                        ))
+                    })
+                }
+            }
+        )*
+    };
+}
+
+integer_key!(i8, i16, i32, i64, i128, u8, u16, u32, u64);
+
+impl Key for OrderedF64 {
+    fn from_arrow(array: &dyn Array, row: usize) -> Result<Self, ArrowError> {
+        check_row(array, row)?;
+        let value = match array.data_type() {
+            DataType::Float32 => array.as_primitive::<Float32Type>().value(row) as f64,
+            DataType::Float64 => array.as_primitive::<Float64Type>().value(row),
+            other => return Err(unsupported(other, "f64")),
+        };
+        Ok(OrderedF64(value))
+    }
+}
+
+impl Key for String {
+    fn from_arrow(array: &dyn Array, row: usize) -> Result<Self, ArrowError> {
+        check_row(array, row)?;
+        let value = match array.data_type() {
+            DataType::Utf8 => array.as_string::<i32>().value(row),
+            DataType::LargeUtf8 => array.as_string::<i64>().value(row),
+            DataType::Utf8View => array.as_string_view().value(row),
+            other => return Err(unsupported(other, "String")),
+        };
+        Ok(value.to_string())
+    }
+}
+
+impl Key for Vec<u8> {
+    fn from_arrow(array: &dyn Array, row: usize) -> Result<Self, ArrowError> {
+        check_row(array, row)?;
+        let value: &[u8] = match array.data_type() {
+            DataType::Binary => array.as_binary::<i32>().value(row),
+            DataType::LargeBinary => array.as_binary::<i64>().value(row),
+            DataType::BinaryView => array.as_binary_view().value(row),
+            DataType::FixedSizeBinary(_) => array.as_fixed_size_binary().value(row),
+            DataType::Utf8 => array.as_string::<i32>().value(row).as_bytes(),
+            DataType::LargeUtf8 => array.as_string::<i64>().value(row).as_bytes(),
+            DataType::Utf8View => array.as_string_view().value(row).as_bytes(),
+            other => return Err(unsupported(other, "Vec<u8>")),
+        };
+        Ok(value.to_vec())
+    }
+}
+
+/// Fixed-width binary keys such as UUIDs or hashes, read from binary columns
+/// whose values are exactly `N` bytes long
+impl<const N: usize> Key for [u8; N] {
+    fn from_arrow(array: &dyn Array, row: usize) -> Result<Self, ArrowError> {
+        let bytes = Vec::<u8>::from_arrow(array, row)?;
+        let len = bytes.len();
+        bytes.try_into().map_err(|_| {
+            ArrowError::CastError(format!("{} bytes do not fit in a [u8; {}] key", len, N))
+        })
+    }
+}
+
+/// Reads the stored value as is: UTC time for columns with a time zone,
+/// wall-clock time for columns without one
+impl Key for NaiveDateTime {
+    fn from_arrow(array: &dyn Array, row: usize) -> Result<Self, ArrowError> {
+        check_row(array, row)?;
+        let value = match array.data_type() {
+            DataType::Timestamp(TimeUnit::Second, _) => {
+                array.as_primitive::<TimestampSecondType>().value_as_datetime(row)
+            }
+            DataType::Timestamp(TimeUnit::Millisecond, _) => {
+                array.as_primitive::<TimestampMillisecondType>().value_as_datetime(row)
+            }
+            DataType::Timestamp(TimeUnit::Microsecond, _) => {
+                array.as_primitive::<TimestampMicrosecondType>().value_as_datetime(row)
+            }
+            DataType::Timestamp(TimeUnit::Nanosecond, _) => {
+                array.as_primitive::<TimestampNanosecondType>().value_as_datetime(row)
+            }
+            other => return Err(unsupported(other, "timestamp")),
+        };
+        value.ok_or_else(|| {
+            ArrowError::CastError(format!("timestamp at row {} is out of range", row))
+        })
+    }
+}
+
+/// Timestamps without a time zone are taken to be in UTC
+impl Key for DateTime<Utc> {
+    fn from_arrow(array: &dyn Array, row: usize) -> Result<Self, ArrowError> {
+        NaiveDateTime::from_arrow(array, row).map(|naive| naive.and_utc())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bplus_tree::BPlusTree;
+    use arrow::array::{
+        BinaryArray, FixedSizeBinaryArray, Float64Array, Int32Array, Int64Array, Scalar,
+        StringArray, TimestampMillisecondArray, UInt64Array,
+    };
+
+    #[test]
+    fn test_integer_keys_are_checked() {
+        let array = Int64Array::from(vec![Some(-3), Some(300), None]);
+        assert_eq!(i8::from_arrow(&array, 0).unwrap(), -3);
+        assert_eq!(i16::from_arrow(&array, 1).unwrap(), 300);
+        assert!(i8::from_arrow(&array, 1).is_err());
+        assert!(u64::from_arrow(&array, 0).is_err());
+        assert!(i64::from_arrow(&array, 2).is_err());
+        assert!(i64::from_arrow(&array, 3).is_err());
+
+        let unsigned = UInt64Array::from(vec![u64::MAX]);
+        assert_eq!(i128::from_arrow(&unsigned, 0).unwrap(), u64::MAX as i128);
+        assert!(i64::from_arrow(&unsigned, 0).is_err());
+
+        assert_eq!(u8::from_scalar(&Scalar::new(Int32Array::from(vec![7]))).unwrap(), 7);
+        assert!(u8::from_scalar(&Int32Array::from(vec![7, 8])).is_err());
+        assert!(i32::from_arrow(&StringArray::from(vec!["7"]), 0).is_err());
+
+        let keys: Vec<u16> = keys_from_arrow(&Int64Array::from(vec![3, 1, 2])).unwrap();
+        let tree: BPlusTree<u16, ()> = keys.into_iter().map(|k| (k, ())).collect();
+        assert_eq!(tree.all_keys(), [1, 2, 3]);
+    }
+
+    #[test]
+    fn test_ordered_f64() {
+        let array = Float64Array::from(vec![2.5, f64::NAN, -0.0, 0.0, f64::NEG_INFINITY]);
+        let keys: Vec<OrderedF64> = keys_from_arrow(&array).unwrap();
+        let tree: BPlusTree<OrderedF64, usize> = keys.into_iter().zip(0..).collect();
+
+        let order: Vec<usize> = tree.values().copied().collect();
+        assert_eq!(order, [4, 2, 3, 0, 1]);
+        assert_eq!(tree.search(&OrderedF64(2.5)), Some(0));
+        assert!(tree.contains_key(&OrderedF64(f64::NAN)));
+        assert!(OrderedF64::from_arrow(&Int64Array::from(vec![1]), 0).is_err());
+    }
+
+    #[test]
+    fn test_string_and_binary_keys() {
+        let names = StringArray::from(vec!["pear", "apple"]);
+        assert_eq!(String::from_arrow(&names, 1).unwrap(), "apple");
+        assert_eq!(Vec::<u8>::from_arrow(&names, 0).unwrap(), b"pear");
+
+        let blobs = BinaryArray::from(vec![&b"\x00\xff"[..]]);
+        assert_eq!(Vec::<u8>::from_arrow(&blobs, 0).unwrap(), [0x00, 0xff]);
+        assert!(String::from_arrow(&blobs, 0).is_err());
+
+        let ids = FixedSizeBinaryArray::try_from_iter([[1u8; 16], [2u8; 16]].into_iter()).unwrap();
+        assert_eq!(Vec::<u8>::from_arrow(&ids, 1).unwrap(), [2u8; 16]);
+        assert_eq!(<[u8; 16]>::from_arrow(&ids, 0).unwrap(), [1u8; 16]);
+        assert!(<[u8; 16]>::from_arrow(&blobs, 0).is_err());
+    }
+
+    #[test]
+    fn test_timestamp_keys() {
+        let millis = TimestampMillisecondArray::from(vec![1_700_000_000_123])
+            .with_timezone("+02:00");
+        let naive = NaiveDateTime::from_arrow(&millis, 0).unwrap();
+        let utc = DateTime::<Utc>::from_arrow(&millis, 0).unwrap();
+        assert_eq!(utc.timestamp_millis(), 1_700_000_000_123);
+        assert_eq!(utc.naive_utc(), naive);
+        assert!(DateTime::<Utc>::from_arrow(&Int64Array::from(vec![0]), 0).is_err());
+    }
+}
@@ -0,0 +1,9 @@
+pub mod batch_builder;
+pub mod bplus_multimap;
+pub mod bplus_persistent;
+pub mod bplus_tree;
+pub mod format;
+pub mod key;
+pub mod profile;
+pub mod tdigest;
+pub mod top_k;
@@ -1,117 +1,175 @@
-use arrow::array::{Int32Builder, Int64Builder, Float64Builder, StringBuilder, BooleanBuilder, RecordBatch, Array};
+use arrow::array::{Array, Float64Array, Int64Array, Int64Builder, RecordBatch, StringBuilder};
 use arrow::datatypes::{DataType, Field, Schema};
+use std::io::Write;
 use std::sync::Arc;
 
-mod bplus_tree;
-use bplus_tree::BPlusTree;
+use rusty_le::batch_builder::{BatchBuilder, Value};
+use rusty_le::bplus_tree::BPlusTree;
+use rusty_le::format::{self, OutputFormat};
+use rusty_le::{profile, tdigest, top_k};
+
+/// `println!` to the output's text stream
+macro_rules! say {
+    ($out:expr) => {
+        writeln!($out.text).expect("Failed to write output")
+    };
+    ($out:expr, $($arg:tt)*) => {
+        writeln!($out.text, $($arg)*).expect("Failed to write output")
+    };
+}
 
 fn main() {
-    println!("========== Example 1: Single Column (Int64) ==========");
-    example1_single_column();
+    let _profile = profile::report_on_drop();
+    let format = match parse_format(std::env::args().skip(1)) {
+        Ok(format) => format,
+        Err(message) => {
+            eprintln!("{}", message);
+            eprintln!("usage: rusty-le [--format table|json|csv|arrow]");
+            std::process::exit(2);
+        }
+    };
+    let mut out = Output::new(format);
+
+    say!(out, "========== Example 1: Single Column (Int64) ==========");
+    example1_single_column(&mut out);
+
+    say!(out, "\n========== Example 2: Multiple Columns ==========");
+    example2_multiple_columns(&mut out);
+
+    say!(out, "\n========== Example 3: Nullable Values ==========");
+    example3_nullable_values(&mut out);
+
+    say!(out, "\n========== Example 4: Different Data Types ==========");
+    example4_mixed_types(&mut out);
 
-    println!("\n========== Example 2: Multiple Columns ==========");
-    example2_multiple_columns();
+    say!(out, "\n========== Example 5: B+ Tree Operations ==========");
+    example5_bplus_tree(&mut out);
 
-    println!("\n========== Example 3: Nullable Values ==========");
-    example3_nullable_values();
+    say!(out, "\n========== Example 6: Approximate Top-K ==========");
+    example6_top_k(&mut out);
 
-    println!("\n========== Example 4: Different Data Types ==========");
-    example4_mixed_types();
+    say!(out, "\n========== Example 7: Approximate Percentiles ==========");
+    example7_percentiles(&mut out);
+}
+
+/// Parse `--format <name>` (or `--format=<name>`) from the command line
+fn parse_format(mut args: impl Iterator<Item = String>) -> Result<OutputFormat, String> {
+    let mut format = OutputFormat::default();
+    while let Some(arg) = args.next() {
+        let value = match arg.strip_prefix("--format") {
+            Some("") => args.next().ok_or("--format requires a value")?,
+            Some(rest) if rest.starts_with('=') => rest[1..].to_string(),
+            _ => return Err(format!("unexpected argument '{}'", arg)),
+        };
+        format = value.parse()?;
+    }
+    Ok(format)
+}
 
-    println!("\n========== Example 5: B+ Tree Operations ==========");
-    example5_bplus_tree();
+/// Where the examples write their text and result sets
+///
+/// Arrow IPC is binary and a stream carries a single schema, so in arrow mode
+/// all text goes to stderr and every result set is written to stdout as its
+/// own IPC stream, one after another. Readers consume them in order by opening
+/// a new stream reader on the same input after each end-of-stream marker.
+struct Output {
+    format: OutputFormat,
+    text: Box<dyn Write>,
+}
+
+impl Output {
+    fn new(format: OutputFormat) -> Self {
+        let text: Box<dyn Write> = match format {
+            OutputFormat::Arrow => Box::new(std::io::stderr()),
+            _ => Box::new(std::io::stdout()),
+        };
+        Output { format, text }
+    }
+
+    /// Print batches sharing one schema in the selected format
+    fn batches(&mut self, batches: &[RecordBatch]) {
+        let result = match self.format {
+            OutputFormat::Arrow => {
+                format::write_batches(std::io::stdout().lock(), batches, OutputFormat::Arrow)
+            }
+            format => format::write_batches(&mut self.text, batches, format),
+        };
+        result.expect("Failed to write batches");
+    }
 }
 
 /// Example 1: Single column with Int64 values
-fn example1_single_column() {
+fn example1_single_column(out: &mut Output) {
     let mut builder = Int64Builder::new();
     builder.append_values(&[1, 2, 3, 4, 5], &[true, true, true, true, true]);
     let array = builder.finish();
 
-    println!("Int64 Array: {:?}", array);
-    println!("Length: {}", array.len());
+    say!(out, "Length: {}", array.len());
 
-    for i in 0..array.len() {
-        if array.is_valid(i) {
-            println!("Value[{}]: {}", i, array.value(i));
-        }
-    }
+    let schema = Arc::new(Schema::new(vec![Field::new("value", DataType::Int64, false)]));
+    let batch = RecordBatch::try_new(schema, vec![Arc::new(array)])
+        .expect("Failed to create RecordBatch");
+    out.batches(&[batch]);
 }
 
 /// Example 2: Multiple columns with different types
-fn example2_multiple_columns() {
+fn example2_multiple_columns(out: &mut Output) {
     let schema = Arc::new(Schema::new(vec![
         Field::new("id", DataType::Int32, false),
         Field::new("name", DataType::Utf8, false),
         Field::new("score", DataType::Float64, false),
     ]));
 
-    let mut id_builder = Int32Builder::new();
-    let mut name_builder = StringBuilder::new();
-    let mut score_builder = Float64Builder::new();
+    let mut builder = BatchBuilder::new(schema.clone())
+        .expect("Schema is supported")
+        .with_max_bytes(1 << 20);
 
     // Append data
-    id_builder.append_values(&[1, 2, 3], &[true, true, true]);
-    name_builder.append_value("Alice");
-    name_builder.append_value("Bob");
-    name_builder.append_value("Charlie");
-    score_builder.append_values(&[95.5, 87.3, 92.1], &[true, true, true]);
-
-    let id_array = id_builder.finish();
-    let name_array = name_builder.finish();
-    let score_array = score_builder.finish();
-
-    let batch = RecordBatch::try_new(
-        schema.clone(),
-        vec![Arc::new(id_array), Arc::new(name_array), Arc::new(score_array)],
-    ).expect("Failed to create RecordBatch");
-
-    println!("Schema: {}", schema);
-    println!("NumRows: {}", batch.num_rows());
-    println!("NumCols: {}", batch.num_columns());
-
-    // Print data
-    for i in 0..batch.num_rows() {
-        let id = batch.column(0)
-            .as_any()
-            .downcast_ref::<arrow::array::Int32Array>()
-            .unwrap()
-            .value(i);
-        let name = batch.column(1)
-            .as_any()
-            .downcast_ref::<arrow::array::StringArray>()
-            .unwrap()
-            .value(i);
-        let score = batch.column(2)
-            .as_any()
-            .downcast_ref::<arrow::array::Float64Array>()
-            .unwrap()
-            .value(i);
-
-        println!("Row {}: ID={}, Name={}, Score={:.2}", i, id, name, score);
+    let rows = [(1, "Alice", 95.5), (2, "Bob", 87.3), (3, "Charlie", 92.1)];
+    for (id, name, score) in rows {
+        builder
+            .append_row(&[Value::Int32(id), Value::Utf8(name), Value::Float64(score)])
+            .expect("Row matches schema");
     }
+
+    let batch = builder
+        .flush()
+        .expect("Failed to create RecordBatch")
+        .expect("Rows were appended");
+
+    say!(out, "Schema: {}", schema);
+    say!(out, "NumRows: {}", batch.num_rows());
+    say!(out, "NumCols: {}", batch.num_columns());
+
+    out.batches(&[batch]);
 }
 
 /// Example 3: Working with nullable values
-fn example3_nullable_values() {
-    let mut builder = Int64Builder::new();
+fn example3_nullable_values(out: &mut Output) {
+    let schema = Arc::new(Schema::new(vec![Field::new("value", DataType::Int64, true)]));
+    let mut builder = BatchBuilder::new(schema).expect("Schema is supported");
     // Append values with some nulls
-    builder.append_values(&[10, 20, 30, 40, 50], &[true, false, true, false, true]);
-    let array = builder.finish();
-
-    println!("Record with nullable values:");
-    for i in 0..array.len() {
-        if array.is_valid(i) {
-            println!("Value[{}]: {}", i, array.value(i));
-        } else {
-            println!("Value[{}]: NULL", i);
-        }
+    for value in [Some(10), None, Some(30), None, Some(50)] {
+        let value = value.map_or(Value::Null, Value::Int64);
+        builder.append_row(&[value]).expect("Row matches schema");
     }
-    println!("Null count: {}", array.null_count());
+    let batch = builder
+        .flush()
+        .expect("Failed to create RecordBatch")
+        .expect("Rows were appended");
+    let array = batch
+        .column(0)
+        .as_any()
+        .downcast_ref::<Int64Array>()
+        .unwrap();
+
+    say!(out, "Record with nullable values:");
+    say!(out, "Null count: {}", array.null_count());
+    out.batches(&[batch]);
 }
 
 /// Example 4: Mixed data types
-fn example4_mixed_types() {
+fn example4_mixed_types(out: &mut Output) {
     let schema = Arc::new(Schema::new(vec![
         Field::new("int_col", DataType::Int32, false),
         Field::new("float_col", DataType::Float64, false),
@@ -119,69 +177,41 @@ fn example4_mixed_types() {
         Field::new("bool_col", DataType::Boolean, false),
     ]));
 
-    let mut int_builder = Int32Builder::new();
-    let mut float_builder = Float64Builder::new();
-    let mut string_builder = StringBuilder::new();
-    let mut bool_builder = BooleanBuilder::new();
+    // Emit a batch every two rows to show threshold-based flushing
+    let mut builder = BatchBuilder::new(schema.clone())
+        .expect("Schema is supported")
+        .with_max_rows(2);
+    let mut batches = Vec::new();
 
     // Append data
-    int_builder.append_values(&[100, 200, 300], &[true, true, true]);
-    float_builder.append_values(&[1.5, 2.5, 3.5], &[true, true, true]);
-    string_builder.append_value("first");
-    string_builder.append_value("second");
-    string_builder.append_value("third");
-    bool_builder.append_values(&[true, false, true], &[true, true, true]).unwrap();
-
-    let int_array = int_builder.finish();
-    let float_array = float_builder.finish();
-    let string_array = string_builder.finish();
-    let bool_array = bool_builder.finish();
-
-    let batch = RecordBatch::try_new(
-        schema.clone(),
-        vec![
-            Arc::new(int_array),
-            Arc::new(float_array),
-            Arc::new(string_array),
-            Arc::new(bool_array),
-        ],
-    ).expect("Failed to create RecordBatch");
-
-    println!("Schema: {}", schema);
-    println!("NumRows: {}", batch.num_rows());
-
-    // Print all rows
-    for i in 0..batch.num_rows() {
-        let int_val = batch.column(0)
-            .as_any()
-            .downcast_ref::<arrow::array::Int32Array>()
-            .unwrap()
-            .value(i);
-        let float_val = batch.column(1)
-            .as_any()
-            .downcast_ref::<arrow::array::Float64Array>()
-            .unwrap()
-            .value(i);
-        let string_val = batch.column(2)
-            .as_any()
-            .downcast_ref::<arrow::array::StringArray>()
-            .unwrap()
-            .value(i);
-        let bool_val = batch.column(3)
-            .as_any()
-            .downcast_ref::<arrow::array::BooleanArray>()
-            .unwrap()
-            .value(i);
-
-        println!("Row {}: int={}, float={:.2}, string={}, bool={}", i, int_val, float_val, string_val, bool_val);
+    let rows = [(100, 1.5, "first", true), (200, 2.5, "second", false), (300, 3.5, "third", true)];
+    for (int_val, float_val, string_val, bool_val) in rows {
+        let row = [
+            Value::Int32(int_val),
+            Value::Float64(float_val),
+            Value::Utf8(string_val),
+            Value::Boolean(bool_val),
+        ];
+        if let Some(batch) = builder.append_row(&row).expect("Row matches schema") {
+            batches.push(batch);
+        }
+    }
+    if let Some(batch) = builder.flush().expect("Failed to create RecordBatch") {
+        batches.push(batch);
     }
+
+    say!(out, "Batches emitted: {}", batches.len());
+    say!(out, "Schema: {}", schema);
+    say!(out, "NumRows: {}", batches.iter().map(|b| b.num_rows()).sum::<usize>());
+
+    out.batches(&batches);
 }
 
 /// Example 5: B+ Tree operations
-fn example5_bplus_tree() {
+fn example5_bplus_tree(out: &mut Output) {
     let mut tree = BPlusTree::new();
 
-    println!("Creating B+ Tree with multiple insertions...");
+    say!(out, "Creating B+ Tree with multiple insertions...");
     
     // Insert key-value pairs
     let data = vec![
@@ -199,41 +229,90 @@ fn example5_bplus_tree() {
 
     for (key, value) in data.iter() {
         tree.insert(*key, value.to_string());
-        println!("Inserted: {} -> {}", key, value);
+        say!(out, "Inserted: {} -> {}", key, value);
     }
 
-    println!("\n{}", tree);
+    say!(out, "\n{}", tree);
     
-    println!("\nTree Structure:");
-    tree.print_tree();
+    say!(out, "\nTree Structure:");
+    tree.write_tree(&mut out.text).expect("Failed to write output");
 
-    println!("\n--- Search Operations ---");
+    say!(out, "\n--- Search Operations ---");
     let search_keys = vec![50, 30, 100, 5];
     for key in search_keys {
-        match tree.search(key) {
-            Some(value) => println!("Found: {} -> {}", key, value),
-            None => println!("Not found: {}", key),
+        match tree.search(&key) {
+            Some(value) => say!(out, "Found: {} -> {}", key, value),
+            None => say!(out, "Not found: {}", key),
         }
     }
 
-    println!("\n--- Range Queries ---");
+    say!(out, "\n--- Range Queries ---");
     let ranges = vec![(10, 40), (30, 70), (1, 100)];
     for (start, end) in ranges {
-        let result = tree.range_query(start, end);
-        println!("Range [{}, {}]:", start, end);
+        let result = tree.range_query(&start, &end);
+        say!(out, "Range [{}, {}]:", start, end);
         for (k, v) in result {
-            println!("  {} -> {}", k, v);
+            say!(out, "  {} -> {}", k, v);
         }
     }
 
-    println!("\n--- All Keys (Sorted) ---");
+    say!(out, "\n--- All Keys (Sorted) ---");
     let all_keys = tree.all_keys();
-    println!("Keys: {:?}", all_keys);
+    say!(out, "Keys: {:?}", all_keys);
 
-    println!("\n--- Update Value ---");
+    say!(out, "\n--- Remove ---");
+    for key in [30, 35, 100] {
+        match tree.remove(&key) {
+            Some(value) => say!(out, "Removed: {} -> {}", key, value),
+            None => say!(out, "Not found: {}", key),
+        }
+    }
+    say!(out, "Keys: {:?}", tree.all_keys());
+
+    say!(out, "\n--- Update Value ---");
     tree.insert(50, "APPLE (updated)".to_string());
-    match tree.search(50) {
-        Some(value) => println!("Updated value: 50 -> {}", value),
-        None => println!("Key not found"),
+    match tree.search(&50) {
+        Some(value) => say!(out, "Updated value: 50 -> {}", value),
+        None => say!(out, "Key not found"),
+    }
+}
+
+/// Example 6: Approximate heavy hitters on a string column
+fn example6_top_k(out: &mut Output) {
+    let mut builder = StringBuilder::new();
+    let pages = ["/home", "/search", "/home", "/cart", "/home", "/search", "/about"];
+    for i in 0..700 {
+        builder.append_value(pages[i % pages.len()]);
+    }
+    builder.append_null();
+    let array = builder.finish();
+
+    say!(out, "Rows: {}, nulls: {}", array.len(), array.null_count());
+    for (value, count) in top_k::top_k(&array, 3) {
+        say!(out, "  {} -> ~{}", value, count);
+    }
+}
+
+/// Example 7: Approximate percentiles, merged across partitions
+fn example7_percentiles(out: &mut Output) {
+    let latencies: Vec<f64> = (0..20_000).map(|i| ((i * 7919) % 1000) as f64 / 10.0).collect();
+    let array = Float64Array::from(latencies);
+
+    for p in [0.5, 0.95, 0.99] {
+        if let Some(value) = tdigest::approx_percentile(&array, p) {
+            say!(out, "p{}: {:.2} ms", p * 100.0, value);
+        }
+    }
+
+    // Build one digest per partition, then merge them
+    let mut merged = tdigest::TDigest::new(100.0);
+    for offset in (0..array.len()).step_by(5_000) {
+        let partition = array.slice(offset, 5_000);
+        let mut digest = tdigest::TDigest::default();
+        for i in 0..partition.len() {
+            digest.add(partition.value(i));
+        }
+        merged.merge(&digest);
     }
+    say!(out, "p99 (merged partitions): {:.2} ms", merged.quantile(0.99).unwrap_or(f64::NAN));
 }
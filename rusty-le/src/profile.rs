@@ -0,0 +1,168 @@
+//! Coarse timing counters for hot paths, enabled by the `profile` feature
+//!
+//! Without the feature a probe is a zero-sized guard that compiles away. With
+//! it, every phase accumulates a call count and total time, and a summary is
+//! printed to stderr when the guard from `report_on_drop`, held by `main`, is
+//! dropped.
+
+/// A hot path whose time is tracked separately
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Phase {
+    /// Picking the child to follow at one internal node on the way to a leaf
+    Descend,
+    /// Locating a key within a leaf's entries
+    LeafSearch,
+    /// Splitting a full node in two
+    Split,
+    /// Finishing buffered rows into a RecordBatch
+    BatchBuild,
+}
+
+impl Phase {
+    pub const ALL: [Phase; 4] = [
+        Phase::Descend,
+        Phase::LeafSearch,
+        Phase::Split,
+        Phase::BatchBuild,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Phase::Descend => "descend",
+            Phase::LeafSearch => "leaf search",
+            Phase::Split => "split",
+            Phase::BatchBuild => "batch build",
+        }
+    }
+}
+
+/// Charges the time until it is dropped to a phase
+#[must_use = "the scope is timed until the guard is dropped"]
+pub struct Scope {
+    #[cfg(feature = "profile")]
+    phase: Phase,
+    #[cfg(feature = "profile")]
+    start: std::time::Instant,
+}
+
+/// Start timing the enclosing scope against `phase`
+#[inline(always)]
+pub fn scope(phase: Phase) -> Scope {
+    #[cfg(feature = "profile")]
+    {
+        Scope {
+            phase,
+            start: std::time::Instant::now(),
+        }
+    }
+    #[cfg(not(feature = "profile"))]
+    {
+        let _ = phase;
+        Scope {}
+    }
+}
+
+/// Prints the profile summary to stderr when dropped
+#[must_use = "the summary is printed when the guard is dropped"]
+pub struct ReportGuard {
+    _private: (),
+}
+
+/// Guard that prints the profile summary when dropped; hold it for the whole
+/// of `main` so the summary covers the run. Without the feature it does nothing.
+pub fn report_on_drop() -> ReportGuard {
+    ReportGuard { _private: () }
+}
+
+#[cfg(feature = "profile")]
+impl Drop for ReportGuard {
+    fn drop(&mut self) {
+        eprint!("{}", report());
+    }
+}
+
+#[cfg(feature = "profile")]
+pub use counters::{report, snapshot};
+
+#[cfg(feature = "profile")]
+impl Drop for Scope {
+    fn drop(&mut self) {
+        counters::record(self.phase, self.start.elapsed());
+    }
+}
+
+#[cfg(feature = "profile")]
+mod counters {
+    use super::Phase;
+    use std::sync::atomic::{AtomicU64, Ordering};
+    use std::time::Duration;
+
+    static CALLS: [AtomicU64; 4] = [const { AtomicU64::new(0) }; 4];
+    static NANOS: [AtomicU64; 4] = [const { AtomicU64::new(0) }; 4];
+
+    pub(super) fn record(phase: Phase, elapsed: Duration) {
+        CALLS[phase as usize].fetch_add(1, Ordering::Relaxed);
+        NANOS[phase as usize].fetch_add(elapsed.as_nanos() as u64, Ordering::Relaxed);
+    }
+
+    /// Calls and total time recorded so far for each phase
+    pub fn snapshot() -> Vec<(Phase, u64, Duration)> {
+        Phase::ALL
+            .iter()
+            .map(|&phase| {
+                let calls = CALLS[phase as usize].load(Ordering::Relaxed);
+                let nanos = NANOS[phase as usize].load(Ordering::Relaxed);
+                (phase, calls, Duration::from_nanos(nanos))
+            })
+            .collect()
+    }
+
+    /// Render the counters as a small aligned table
+    pub fn report() -> String {
+        let mut out = format!(
+            "{:<12} {:>12} {:>12} {:>10}\n",
+            "phase", "calls", "total ms", "avg ns"
+        );
+        for (phase, calls, total) in snapshot() {
+            let avg = if calls == 0 { 0 } else { total.as_nanos() / calls as u128 };
+            out.push_str(&format!(
+                "{:<12} {:>12} {:>12.3} {:>10}\n",
+                phase.name(),
+                calls,
+                total.as_secs_f64() * 1000.0,
+                avg
+            ));
+        }
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(not(feature = "profile"))]
+    #[test]
+    fn test_disabled_scope_is_free() {
+        assert_eq!(std::mem::size_of::<Scope>(), 0);
+        assert!(!std::mem::needs_drop::<Scope>());
+    }
+
+    #[cfg(feature = "profile")]
+    #[test]
+    fn test_scopes_are_counted() {
+        let calls = |phase: Phase| {
+            snapshot()
+                .into_iter()
+                .find(|(p, _, _)| *p == phase)
+                .map(|(_, calls, _)| calls)
+                .unwrap()
+        };
+        let before = calls(Phase::Split);
+        for _ in 0..3 {
+            let _scope = scope(Phase::Split);
+        }
+        assert!(calls(Phase::Split) >= before + 3);
+        assert!(report().contains("split"));
+    }
+}
@@ -0,0 +1,212 @@
+use arrow::array::{Array, Float64Array};
+use std::f64::consts::PI;
+
+/// Default compression; higher keeps more centroids and is more accurate
+const DEFAULT_COMPRESSION: f64 = 100.0;
+
+/// Weighted cluster of nearby values
+#[derive(Clone, Copy, Debug)]
+struct Centroid {
+    mean: f64,
+    weight: f64,
+}
+
+/// Merging t-digest for approximate quantiles
+///
+/// Values are buffered and periodically compressed into centroids that are
+/// small near the tails and large near the median, so extreme percentiles stay
+/// accurate. Digests built on separate partitions can be merged.
+#[derive(Clone, Debug)]
+pub struct TDigest {
+    compression: f64,
+    centroids: Vec<Centroid>,
+    buffer: Vec<f64>,
+    count: f64,
+    min: f64,
+    max: f64,
+}
+
+impl TDigest {
+    /// Create an empty digest with the given compression factor
+    pub fn new(compression: f64) -> Self {
+        TDigest {
+            compression: compression.max(1.0),
+            centroids: Vec::new(),
+            buffer: Vec::new(),
+            count: 0.0,
+            min: f64::INFINITY,
+            max: f64::NEG_INFINITY,
+        }
+    }
+
+    /// Add a single value; NaN is ignored
+    pub fn add(&mut self, value: f64) {
+        if value.is_nan() {
+            return;
+        }
+        self.buffer.push(value);
+        self.count += 1.0;
+        self.min = self.min.min(value);
+        self.max = self.max.max(value);
+        if self.buffer.len() >= self.buffer_limit() {
+            self.compress();
+        }
+    }
+
+    /// Fold another digest (e.g. from a different partition) into this one
+    pub fn merge(&mut self, other: &TDigest) {
+        self.centroids.extend(other.centroids.iter().copied());
+        self.buffer.extend(other.buffer.iter().copied());
+        self.count += other.count;
+        self.min = self.min.min(other.min);
+        self.max = self.max.max(other.max);
+        self.compress();
+    }
+
+    /// Estimate the value at quantile `q` in [0, 1]
+    pub fn quantile(&self, q: f64) -> Option<f64> {
+        if self.count == 0.0 || !(0.0..=1.0).contains(&q) {
+            return None;
+        }
+
+        let mut digest = self.clone();
+        digest.compress();
+        let centroids = &digest.centroids;
+        if centroids.len() == 1 {
+            return Some(centroids[0].mean);
+        }
+
+        let target = q * digest.count;
+        let first = centroids[0];
+        if target <= first.weight / 2.0 {
+            let frac = target / (first.weight / 2.0);
+            return Some(digest.min + frac * (first.mean - digest.min));
+        }
+
+        let mut cumulative = 0.0;
+        for pair in centroids.windows(2) {
+            let left = cumulative + pair[0].weight / 2.0;
+            let right = cumulative + pair[0].weight + pair[1].weight / 2.0;
+            if target <= right {
+                let frac = (target - left) / (right - left);
+                return Some(pair[0].mean + frac * (pair[1].mean - pair[0].mean));
+            }
+            cumulative += pair[0].weight;
+        }
+
+        let last = centroids[centroids.len() - 1];
+        let left = digest.count - last.weight / 2.0;
+        let frac = ((target - left) / (last.weight / 2.0)).min(1.0);
+        Some(last.mean + frac * (digest.max - last.mean))
+    }
+
+    fn buffer_limit(&self) -> usize {
+        (self.compression * 5.0) as usize
+    }
+
+    /// Scale function mapping a quantile to its centroid index space
+    fn k_scale(&self, q: f64) -> f64 {
+        self.compression / (2.0 * PI) * (2.0 * q - 1.0).asin()
+    }
+
+    fn compress(&mut self) {
+        if self.buffer.is_empty() && self.centroids.len() <= 1 {
+            return;
+        }
+
+        let mut all: Vec<Centroid> = self.centroids.drain(..).collect();
+        all.extend(self.buffer.drain(..).map(|v| Centroid {
+            mean: v,
+            weight: 1.0,
+        }));
+        all.sort_by(|a, b| a.mean.total_cmp(&b.mean));
+
+        let total = self.count;
+        let mut merged = Vec::new();
+        let mut current = all[0];
+        let mut weight_so_far = 0.0;
+        let mut k_lower = self.k_scale(0.0);
+
+        for next in all.into_iter().skip(1) {
+            let q = (weight_so_far + current.weight + next.weight) / total;
+            if self.k_scale(q.min(1.0)) - k_lower <= 1.0 {
+                let weight = current.weight + next.weight;
+                current.mean += (next.mean - current.mean) * next.weight / weight;
+                current.weight = weight;
+            } else {
+                weight_so_far += current.weight;
+                k_lower = self.k_scale((weight_so_far / total).min(1.0));
+                merged.push(current);
+                current = next;
+            }
+        }
+        merged.push(current);
+        self.centroids = merged;
+    }
+}
+
+impl Default for TDigest {
+    fn default() -> Self {
+        Self::new(DEFAULT_COMPRESSION)
+    }
+}
+
+/// Approximate percentile of a float column, skipping nulls
+pub fn approx_percentile(array: &Float64Array, p: f64) -> Option<f64> {
+    let mut digest = TDigest::default();
+    for i in 0..array.len() {
+        if array.is_valid(i) {
+            digest.add(array.value(i));
+        }
+    }
+    digest.quantile(p)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(actual: f64, expected: f64, tolerance: f64) {
+        assert!(
+            (actual - expected).abs() <= tolerance,
+            "expected {} within {} of {}",
+            actual,
+            tolerance,
+            expected
+        );
+    }
+
+    #[test]
+    fn test_uniform_percentiles() {
+        let array = Float64Array::from((0..10_000).map(|i| i as f64).collect::<Vec<_>>());
+
+        assert_close(approx_percentile(&array, 0.5).unwrap(), 5000.0, 50.0);
+        assert_close(approx_percentile(&array, 0.99).unwrap(), 9900.0, 20.0);
+        assert_eq!(approx_percentile(&array, 0.0), Some(0.0));
+        assert_eq!(approx_percentile(&array, 1.0), Some(9999.0));
+    }
+
+    #[test]
+    fn test_merge_partitions() {
+        let mut left = TDigest::default();
+        let mut right = TDigest::default();
+        for i in 0..5_000 {
+            left.add(i as f64);
+            right.add((i + 5_000) as f64);
+        }
+
+        left.merge(&right);
+        assert_close(left.quantile(0.5).unwrap(), 5000.0, 50.0);
+        assert_close(left.quantile(0.9).unwrap(), 9000.0, 50.0);
+    }
+
+    #[test]
+    fn test_empty_and_invalid() {
+        let digest = TDigest::default();
+        assert_eq!(digest.quantile(0.5), None);
+
+        let array = Float64Array::from(vec![Some(1.0), None, Some(3.0)]);
+        assert_eq!(approx_percentile(&array, 1.5), None);
+        assert_eq!(approx_percentile(&array, 1.0), Some(3.0));
+    }
+}
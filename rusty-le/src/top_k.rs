@@ -0,0 +1,212 @@
+use arrow::array::{Array, StringArray};
+use std::collections::HashMap;
+use std::sync::Arc;
+
+/// Number of counters tracked per requested result
+const CAPACITY_FACTOR: usize = 10;
+
+/// Counter for a monitored item
+#[derive(Clone, Debug)]
+struct Counter {
+    item: Arc<str>,
+    count: u64,
+    error: u64,
+}
+
+/// Space-Saving sketch for approximate heavy hitters
+///
+/// Keeps at most `capacity` counters. When a new item arrives and the sketch
+/// is full, the smallest counter is evicted and its count becomes the error
+/// bound of the newcomer, so reported counts never underestimate. Counters sit
+/// in a min-heap ordered by count, with an index from item to heap slot, so
+/// both counting an item and evicting the smallest cost O(log capacity).
+pub struct SpaceSaving {
+    capacity: usize,
+    heap: Vec<Counter>,
+    slots: HashMap<Arc<str>, usize>,
+}
+
+impl SpaceSaving {
+    /// Create a sketch that monitors at most `capacity` distinct items
+    pub fn new(capacity: usize) -> Self {
+        let capacity = capacity.max(1);
+        SpaceSaving {
+            capacity,
+            heap: Vec::with_capacity(capacity),
+            slots: HashMap::with_capacity(capacity),
+        }
+    }
+
+    /// Record one occurrence of an item
+    pub fn insert(&mut self, item: &str) {
+        if let Some(&slot) = self.slots.get(item) {
+            self.heap[slot].count += 1;
+            self.sift_down(slot);
+            return;
+        }
+
+        let item: Arc<str> = Arc::from(item);
+        if self.heap.len() < self.capacity {
+            self.slots.insert(item.clone(), self.heap.len());
+            self.heap.push(Counter {
+                item,
+                count: 1,
+                error: 0,
+            });
+            self.sift_up(self.heap.len() - 1);
+            return;
+        }
+
+        // The smallest counter sits at the root; the newcomer takes it over
+        let min_count = self.heap[0].count;
+        let newcomer = Counter {
+            item: item.clone(),
+            count: min_count + 1,
+            error: min_count,
+        };
+        let victim = std::mem::replace(&mut self.heap[0], newcomer);
+        self.slots.remove(&victim.item);
+        self.slots.insert(item, 0);
+        self.sift_down(0);
+    }
+
+    fn sift_up(&mut self, mut slot: usize) {
+        while slot > 0 {
+            let parent = (slot - 1) / 2;
+            if self.heap[parent].count <= self.heap[slot].count {
+                break;
+            }
+            self.swap(parent, slot);
+            slot = parent;
+        }
+    }
+
+    fn sift_down(&mut self, mut slot: usize) {
+        loop {
+            let mut smallest = slot;
+            for child in [2 * slot + 1, 2 * slot + 2] {
+                if child < self.heap.len() && self.heap[child].count < self.heap[smallest].count {
+                    smallest = child;
+                }
+            }
+            if smallest == slot {
+                return;
+            }
+            self.swap(slot, smallest);
+            slot = smallest;
+        }
+    }
+
+    /// Swap two heap slots, keeping the item index in step
+    fn swap(&mut self, a: usize, b: usize) {
+        self.heap.swap(a, b);
+        for slot in [a, b] {
+            *self
+                .slots
+                .get_mut(&self.heap[slot].item)
+                .expect("every counter is indexed") = slot;
+        }
+    }
+
+    /// The `k` most frequent items as (item, estimated count, max overestimate)
+    pub fn top_k(&self, k: usize) -> Vec<(String, u64, u64)> {
+        let mut result: Vec<(String, u64, u64)> = self
+            .heap
+            .iter()
+            .map(|c| (c.item.to_string(), c.count, c.error))
+            .collect();
+        result.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+        result.truncate(k);
+        result
+    }
+}
+
+/// Approximate top-k values of a string column, skipping nulls
+pub fn top_k(array: &StringArray, k: usize) -> Vec<(String, u64)> {
+    // A column never has more distinct values than rows
+    let capacity = k.saturating_mul(CAPACITY_FACTOR).min(array.len());
+    let mut sketch = SpaceSaving::new(capacity);
+    for i in 0..array.len() {
+        if array.is_valid(i) {
+            sketch.insert(array.value(i));
+        }
+    }
+    sketch
+        .top_k(k)
+        .into_iter()
+        .map(|(item, count, _)| (item, count))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_exact_when_under_capacity() {
+        let array = StringArray::from(vec![
+            Some("a"),
+            Some("b"),
+            Some("a"),
+            None,
+            Some("c"),
+            Some("a"),
+            Some("b"),
+        ]);
+
+        let result = top_k(&array, 2);
+        assert_eq!(
+            result,
+            vec![("a".to_string(), 3), ("b".to_string(), 2)]
+        );
+    }
+
+    #[test]
+    fn test_huge_k_returns_every_value() {
+        let array = StringArray::from(vec!["x", "y", "x"]);
+
+        let result = top_k(&array, usize::MAX);
+        assert_eq!(
+            result,
+            vec![("x".to_string(), 2), ("y".to_string(), 1)]
+        );
+    }
+
+    #[test]
+    fn test_heavy_hitter_survives_eviction() {
+        let mut sketch = SpaceSaving::new(4);
+        for i in 0..1000 {
+            sketch.insert("hot");
+            sketch.insert(&format!("cold_{}", i));
+        }
+
+        let result = sketch.top_k(1);
+        assert_eq!(result[0].0, "hot");
+        assert!(result[0].1 >= 1000);
+        assert!(result[0].1 - result[0].2 <= 1000);
+    }
+
+    #[test]
+    fn test_counts_stay_bounded_on_high_cardinality() {
+        let mut sketch = SpaceSaving::new(16);
+        let mut exact: HashMap<String, u64> = HashMap::new();
+        for i in 0..20_000u64 {
+            // A few hot items over a long tail of distinct ones
+            let item = if i % 3 == 0 { format!("hot_{}", i % 5) } else { format!("tail_{}", i) };
+            sketch.insert(&item);
+            *exact.entry(item).or_default() += 1;
+        }
+
+        assert_eq!(sketch.heap.len(), 16);
+        assert_eq!(sketch.heap.iter().map(|c| c.count).sum::<u64>(), 20_000);
+        for (slot, counter) in sketch.heap.iter().enumerate() {
+            assert_eq!(sketch.slots[&counter.item], slot);
+            assert!(slot == 0 || sketch.heap[(slot - 1) / 2].count <= counter.count);
+            let true_count = exact[&*counter.item];
+            assert!(counter.count >= true_count);
+            assert!(counter.count - counter.error <= true_count);
+        }
+        let top: Vec<String> = sketch.top_k(5).into_iter().map(|(item, _, _)| item).collect();
+        assert!(top.iter().all(|item| item.starts_with("hot_")), "{:?}", top);
+    }
+}
@@ -0,0 +1,106 @@
+//! Allocations per tree operation, counted by a global allocator that is only
+//! installed in this test binary. Run with `--nocapture` to see the report.
+
+use rusty_le::bplus_tree::BPlusTree;
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::cell::Cell;
+
+/// Counts allocations per thread so parallel tests do not see each other's
+struct CountingAllocator;
+
+thread_local! {
+    static ALLOCATIONS: Cell<usize> = const { Cell::new(0) };
+}
+
+fn record() {
+    let _ = ALLOCATIONS.try_with(|count| count.set(count.get() + 1));
+}
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        record();
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        record();
+        System.realloc(ptr, layout, new_size)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAllocator = CountingAllocator;
+
+/// Run `f` and return its result with the number of allocations it made
+fn count_allocations<R>(f: impl FnOnce() -> R) -> (R, usize) {
+    let before = ALLOCATIONS.with(Cell::get);
+    let result = f();
+    (result, ALLOCATIONS.with(Cell::get) - before)
+}
+
+fn report(operation: &str, ops: usize, allocations: usize) {
+    println!(
+        "{:<14} {:>4} ops {:>5} allocations {:>6.2} per op",
+        operation,
+        ops,
+        allocations,
+        allocations as f64 / ops as f64
+    );
+}
+
+fn sample_tree() -> BPlusTree {
+    let mut tree = BPlusTree::new();
+    for i in 1..=12 {
+        tree.insert(i, format!("value_{}", i));
+    }
+    tree
+}
+
+#[test]
+fn insert_and_remove() {
+    let values: Vec<String> = (1..=12).map(|i| format!("value_{}", i)).collect();
+    let mut tree = BPlusTree::new();
+    let ((), inserts) = count_allocations(|| {
+        for (i, value) in values.into_iter().enumerate() {
+            tree.insert(i as i32 + 1, value);
+        }
+    });
+    report("insert", 12, inserts);
+    // Leaf growth and splits only; keys and values are moved, never cloned
+    assert!(inserts <= 2 * 12, "insert allocated {} times", inserts);
+
+    let ((), removes) = count_allocations(|| {
+        for i in 1..=12 {
+            tree.remove(&i);
+        }
+    });
+    report("remove", 12, removes);
+    assert!(removes <= 12, "remove allocated {} times", removes);
+}
+
+#[test]
+fn lookups() {
+    let tree = sample_tree();
+    let (_, contains) = count_allocations(|| (0..=13).filter(|k| tree.contains_key(k)).count());
+    report("contains_key", 14, contains);
+    assert_eq!(contains, 0);
+    let (_, search) = count_allocations(|| (1..=12).filter_map(|k| tree.search(&k)).count());
+    report("search", 12, search);
+    // One clone of each found value
+    assert_eq!(search, 12);
+}
+
+#[test]
+fn scans() {
+    let tree = sample_tree();
+    let (_, iter) = count_allocations(|| tree.iter().count());
+    report("iter", 12, iter);
+    assert_eq!(iter, 0);
+    let (_, range) = count_allocations(|| tree.range(3..9).rev().count());
+    report("range", 6, range);
+    assert_eq!(range, 0);
+}